@@ -0,0 +1,441 @@
+//! 型の別名と修飾子変数の解決
+//!
+//! パース後の抽象構文木に含まれる型の別名を、typeで定義した型に置き換える。
+//! 別名はtypeに続く式の中でのみ有効で、内側の定義は外側の同名の定義を隠す。
+//! 定義されていない別名と、自身の定義の中で自身を参照する別名はエラーとする。
+//!
+//! 修飾子変数と型変数は、forallで量化した型の中と、letやdefの型注釈で量化した場合は
+//! 束縛する値の式の中で有効で、それ以外の修飾子変数はエラーとする。
+//! 型変数は別名と同じく名前で参照し、PrimType::Varに置き換える。
+//!
+//! 複数の引数を持つ関数は、引数の型を解決した後に関数の入れ子に変換する。
+
+use crate::{
+    error::{Error, ErrorKind},
+    parser::{Expr, FnExpr, PrimType, QValExpr, Qual, Span, TypeExpr, TypeParam, ValExpr},
+};
+use std::mem;
+
+/// 式中の型の別名と型変数を全て解決し、修飾子変数が量化されているかチェック
+pub fn resolve(expr: &mut Expr) -> Result<(), Error> {
+    resolve_expr(expr, &mut Names::default())
+}
+
+/// 有効な名前
+#[derive(Debug, Default)]
+struct Names {
+    aliases: Vec<(String, TypeExpr)>, // 別名と、別名が表す解決済みの型。後ろにあるものほど内側の定義
+    params: Vec<TypeParam>,           // 量化された修飾子変数と型変数
+}
+
+impl Names {
+    /// 修飾子変数qが量化されているか
+    fn has_qual(&self, q: &str) -> bool {
+        self.params.iter().any(|p| p.name == q && p.bound.is_none())
+    }
+
+    /// 量化された型変数nameの修飾子の上限
+    fn type_var(&self, name: &str) -> Option<&Qual> {
+        self.params
+            .iter()
+            .rev()
+            .find(|p| p.name == name)
+            .and_then(|p| p.bound.as_ref())
+    }
+}
+
+fn resolve_expr(expr: &mut Expr, names: &mut Names) -> Result<(), Error> {
+    let span = expr.span();
+    match expr {
+        Expr::Let(e) => {
+            if let Some(ty) = &mut e.ty {
+                resolve_type(ty, names, None, span)?;
+            }
+            resolve_bound(&e.ty, &mut e.expr1, names)?;
+            resolve_expr(&mut e.expr2, names)
+        }
+        Expr::If(e) => {
+            resolve_expr(&mut e.cond_expr, names)?;
+            resolve_expr(&mut e.then_expr, names)?;
+            resolve_expr(&mut e.else_expr, names)
+        }
+        Expr::Split(e) => {
+            resolve_expr(&mut e.expr, names)?;
+            resolve_expr(&mut e.body, names)
+        }
+        Expr::Case(e) => {
+            resolve_expr(&mut e.expr, names)?;
+            resolve_expr(&mut e.inl_expr, names)?;
+            resolve_expr(&mut e.inr_expr, names)
+        }
+        Expr::Free(e) => resolve_expr(&mut e.expr, names),
+        Expr::App(e) => {
+            resolve_expr(&mut e.expr1, names)?;
+            resolve_expr(&mut e.expr2, names)
+        }
+        Expr::BinOp(e) => {
+            resolve_expr(&mut e.expr1, names)?;
+            resolve_expr(&mut e.expr2, names)
+        }
+        Expr::Var(_) => Ok(()),
+        Expr::QVal(e) => match &mut e.val {
+            ValExpr::Bool(_) | ValExpr::Int(_) | ValExpr::Unit => Ok(()),
+            ValExpr::Pair(e1, e2) => {
+                resolve_expr(e1, names)?;
+                resolve_expr(e2, names)
+            }
+            ValExpr::Inl(e) | ValExpr::Inr(e) => {
+                resolve_type(&mut e.left_ty, names, None, span)?;
+                resolve_type(&mut e.right_ty, names, None, span)?;
+                resolve_expr(&mut e.expr, names)
+            }
+            ValExpr::Fun(f) => {
+                resolve_type(&mut f.ty, names, None, span)?;
+                for p in f.rest.iter_mut() {
+                    resolve_type(&mut p.ty, names, None, span)?;
+                }
+                curry(&e.qual, f);
+                resolve_expr(&mut f.expr, names)
+            }
+        },
+        Expr::Def(e) => {
+            if let Some(ty) = &mut e.ty {
+                resolve_type(ty, names, None, span)?;
+            }
+            resolve_bound(&e.ty, &mut e.expr, names)
+        }
+        Expr::Env(e) => resolve_expr(&mut e.expr, names),
+        Expr::Type(e) => {
+            resolve_type(&mut e.ty, names, Some(&e.name), span)?;
+            names.aliases.push((e.name.clone(), e.ty.clone()));
+            let ret = resolve_expr(&mut e.expr, names);
+            names.aliases.pop();
+            ret
+        }
+        Expr::Seq(e) => {
+            resolve_expr(&mut e.expr1, names)?;
+            resolve_expr(&mut e.expr2, names)
+        }
+    }
+}
+
+/// 型注釈tyを持つletやdefの束縛する値exprを解決
+///
+/// 型注釈で量化した修飾子変数と型変数は、expr中の関数の引数の型などに書ける
+fn resolve_bound(ty: &Option<TypeExpr>, expr: &mut Expr, names: &mut Names) -> Result<(), Error> {
+    let len = names.params.len();
+    if let Some(TypeExpr {
+        prim: PrimType::Forall(params, _),
+        ..
+    }) = ty
+    {
+        names.params.extend(params.iter().cloned());
+    }
+    let ret = resolve_expr(expr, names);
+    names.params.truncate(len);
+    ret
+}
+
+/// 複数の引数を持つ関数fを、引数を一つずつ受け取る関数の入れ子に変換。qはfの修飾子
+///
+/// 二つめ以降の引数を受け取る関数の修飾子は、qとそれまでの引数の修飾子のうち
+/// 最も制約の強いものとする。それまでの引数をキャプチャするため、
+/// lin型の引数をキャプチャする関数はlin型でなければならない。
+/// 値の修飾子に修飾子変数は書けないため、修飾子変数の場合はlinとする。
+/// 引数の型は解決済みでなければならない
+///
+/// ```text
+/// q fn x : lin bool, y : un bool { e } => q fn x : lin bool { lin fn y : un bool { e } }
+/// ```
+fn curry(q: &Qual, f: &mut FnExpr) {
+    let rest = mem::take(&mut f.rest);
+
+    // 各引数を受け取る関数の修飾子
+    let mut quals = Vec::new();
+    let mut acc = q.clone();
+    for ty in [&f.ty].into_iter().chain(rest.iter().map(|p| &p.ty)) {
+        acc = match acc.join(&ty.qual) {
+            Qual::Var(_) => Qual::Lin,
+            q => q,
+        };
+        quals.push(acc.clone());
+    }
+
+    // 最後の引数から順に関数の入れ子にする
+    let unit = Expr::QVal(QValExpr {
+        qual: Qual::Un,
+        val: ValExpr::Unit,
+        span: Span::default(),
+    });
+    let mut expr = mem::replace(&mut *f.expr, unit);
+    for (p, q) in rest.into_iter().zip(quals).rev() {
+        expr = Expr::QVal(QValExpr {
+            qual: q,
+            val: ValExpr::Fun(FnExpr {
+                var: p.var,
+                ty: p.ty,
+                expr: Box::new(expr),
+                rest: Vec::new(),
+            }),
+            span: p.span,
+        });
+    }
+    *f.expr = expr;
+}
+
+/// 型中の別名と型変数を解決。definingは定義中の別名、spanは型を含む式の位置
+fn resolve_type(
+    ty: &mut TypeExpr,
+    names: &mut Names,
+    defining: Option<&str>,
+    span: Span,
+) -> Result<(), Error> {
+    // 量化されていない修飾子変数。forallで量化した型の修飾子はforallの中で調べる
+    match (&ty.qual, &ty.prim) {
+        (_, PrimType::Forall(_, _)) | (_, PrimType::Alias(_)) | (_, PrimType::Var(_)) => (),
+        (Qual::Var(q), _) if !names.has_qual(q) => {
+            return Err(Error::new(ErrorKind::UnboundQualifier(q.clone()), span));
+        }
+        _ => (),
+    }
+
+    match &mut ty.prim {
+        PrimType::Alias(name) => {
+            if Some(name.as_str()) == defining {
+                return Err(Error::new(ErrorKind::RecursiveType(name.clone()), span));
+            }
+            // 型変数は別名より優先する
+            if let Some(bound) = names.type_var(name) {
+                *ty = TypeExpr {
+                    qual: bound.clone(),
+                    prim: PrimType::Var(name.clone()),
+                };
+                return Ok(());
+            }
+            match names.aliases.iter().rev().find(|(n, _)| n == name) {
+                Some((_, t)) => *ty = t.clone(),
+                None => return Err(Error::new(ErrorKind::UnknownType(name.clone()), span)),
+            }
+        }
+        PrimType::Pair(t1, t2) | PrimType::Sum(t1, t2) | PrimType::Arrow(t1, t2) => {
+            resolve_type(t1, names, defining, span)?;
+            resolve_type(t2, names, defining, span)?;
+        }
+        PrimType::Forall(params, prim) => {
+            // 量化した型の修飾子は外側の型の修飾子
+            let mut t = TypeExpr {
+                qual: ty.qual.clone(),
+                prim: (**prim).clone(),
+            };
+            let len = names.params.len();
+            names.params.extend(params.iter().cloned());
+            let ret = resolve_type(&mut t, names, defining, span);
+            names.params.truncate(len);
+            ret?;
+            **prim = t.prim;
+            ty.qual = t.qual;
+        }
+        PrimType::Bool | PrimType::Int | PrimType::Unit | PrimType::Var(_) => (),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{alias::*, parser};
+
+    // エラーの種類を直接比較するため、Boxを外して返す
+    #[allow(clippy::result_large_err)]
+    fn resolve_str(input: &str) -> Result<String, ErrorKind> {
+        let (_, mut expr) = parser::parse(input).unwrap();
+        resolve(&mut expr).map_err(|e| *e.kind)?;
+        match expr {
+            Expr::Type(e) => Ok(e.expr.to_string()),
+            e => Ok(e.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        // 別名は修飾子を含めた型全体を表す
+        assert_eq!(
+            Ok("lin fn x : lin (lin bool * lin bool) {\n    x\n}".to_string()),
+            resolve_str("type P = lin (lin bool * lin bool); lin fn x : P { x }")
+        );
+
+        // 別名の定義の中では、それまでに定義した別名を使える
+        assert_eq!(
+            Ok("type F = un (lin (lin bool * lin bool) -> lin bool);\n\
+                let f : un (lin (lin bool * lin bool) -> lin bool) = g;\n\
+                (f p)"
+                .to_string()),
+            resolve_str(
+                "type P = lin (lin bool * lin bool);
+                type F = un (P -> lin bool);
+                let f : F = g; (f p)"
+            )
+        );
+
+        // 内側の定義は外側の定義を隠し、スコープを抜けると元に戻る
+        assert_eq!(
+            Ok("un <type T = un int;\nun inl (un int + un int) un 1, \
+                un inl (un bool + un bool) un true>"
+                .to_string()),
+            resolve_str(
+                "type T = un bool;
+                un <type T = un int; un inl (T + T) un 1, un inl (T + T) un true>"
+            )
+        );
+    }
+
+    #[test]
+    fn test_resolve_multi_arg() {
+        // 複数の引数を持つ関数は関数の入れ子
+        assert_eq!(
+            resolve_str("un fn x : lin bool { lin fn y : un bool { lin fn z : aff int { x } } }"),
+            resolve_str("un fn x : lin bool, y : un bool, z : aff int { x }")
+        );
+        assert_eq!(
+            resolve_str("aff fn x : un bool { aff fn y : un bool { x } }"),
+            resolve_str("aff fn x : un bool,y : un bool { x }")
+        );
+
+        // 入れ子にした関数の修飾子は、解決後の引数の型の修飾子から決める
+        assert_eq!(
+            Ok("un fn x : lin bool {\n    lin fn y : un bool {\n        x\n    }\n}".to_string()),
+            resolve_str("type L = lin bool; un fn x : L, y : un bool { x }")
+        );
+        let (_, mut expr) =
+            parser::parse("let k : forall a : lin. un (a -> lin (un bool -> a)) = un fn x : a, y : un bool { x }; k")
+                .unwrap();
+        resolve(&mut expr).unwrap();
+        let f = match expr {
+            Expr::Let(e) => *e.expr1,
+            _ => panic!("let expression expected"),
+        };
+        match f {
+            Expr::QVal(QValExpr {
+                qual: Qual::Un,
+                val: ValExpr::Fun(FnExpr { expr, rest, .. }),
+                ..
+            }) => {
+                assert!(rest.is_empty());
+                assert!(matches!(
+                    *expr,
+                    Expr::QVal(QValExpr {
+                        qual: Qual::Lin,
+                        ..
+                    })
+                ));
+            }
+            _ => panic!("function expected"),
+        }
+    }
+
+    #[test]
+    fn test_resolve_type_var() {
+        // 型変数は修飾子の上限を修飾子とするPrimType::Varとなる
+        let (_, mut expr) = parser::parse("let f : forall a : aff. un (a -> a) = g; f").unwrap();
+        resolve(&mut expr).unwrap();
+        let ty = match expr {
+            Expr::Let(e) => e.ty.unwrap(),
+            _ => panic!("let expression expected"),
+        };
+        let arrow = match ty.prim {
+            PrimType::Forall(_, prim) => *prim,
+            _ => panic!("forall type expected"),
+        };
+        match arrow {
+            PrimType::Arrow(t1, _) => assert_eq!(
+                TypeExpr {
+                    qual: Qual::Aff,
+                    prim: PrimType::Var("a".to_string()),
+                },
+                *t1
+            ),
+            _ => panic!("function type expected"),
+        }
+
+        // 型変数は同名の別名より優先する
+        assert_eq!(
+            Ok("let f : forall a : lin. un (a -> a) = un fn x : a {\n    x\n};\nf".to_string()),
+            resolve_str(
+                "type a = un bool; let f : forall a : lin. un (a -> a) = un fn x : a { x }; f"
+            )
+        );
+    }
+
+    #[test]
+    fn test_resolve_error() {
+        assert_eq!(
+            Err(ErrorKind::UnknownType("Pair".to_string())),
+            resolve_str("let x : Pair = un true; x")
+        );
+        // スコープ外の別名
+        assert_eq!(
+            Err(ErrorKind::UnknownType("B".to_string())),
+            resolve_str("un <type B = un bool; un true, un fn x : B { x }>")
+        );
+        // 再帰的な別名
+        assert_eq!(
+            Err(ErrorKind::RecursiveType("L".to_string())),
+            resolve_str("type L = un (un int * L); un 1")
+        );
+        assert_eq!(
+            Err(ErrorKind::RecursiveType("B".to_string())),
+            resolve_str("type B = un bool; type B = un (B -> B); un 1")
+        );
+
+        // 量化されていない修飾子変数
+        assert_eq!(
+            Err(ErrorKind::UnboundQualifier("ln".to_string())),
+            resolve_str("lin fn x : ln bool { x }")
+        );
+        assert_eq!(
+            Err(ErrorKind::UnboundQualifier("p".to_string())),
+            resolve_str("let f : forall q. un (q bool -> p bool) = g; f")
+        );
+        // 型注釈で量化した修飾子変数は、束縛する値の中でのみ有効
+        assert!(resolve_str(
+            "let id : forall q. un (q bool -> q bool) = un fn x : q bool { x }; id"
+        )
+        .is_ok());
+        assert_eq!(
+            Err(ErrorKind::UnboundQualifier("q".to_string())),
+            resolve_str(
+                "let id : forall q. un (q bool -> q bool) = un fn x : q bool { x };
+                un fn y : q bool { y }"
+            )
+        );
+
+        // 型変数は量化した型と、型注釈で量化した場合は束縛する値の中でのみ有効
+        assert!(
+            resolve_str("let id : forall a : lin. un (a -> a) = un fn x : a { x }; id").is_ok()
+        );
+        assert_eq!(
+            Err(ErrorKind::UnknownType("a".to_string())),
+            resolve_str(
+                "let id : forall a : lin. un (a -> a) = un fn x : a { x };
+                un fn y : a { y }"
+            )
+        );
+        // 修飾子変数は型として、型変数は修飾子として使えない
+        assert_eq!(
+            Err(ErrorKind::UnknownType("q".to_string())),
+            resolve_str("let f : forall q. un (q -> q bool) = g; f")
+        );
+        assert_eq!(
+            Err(ErrorKind::UnboundQualifier("a".to_string())),
+            resolve_str("let f : forall a : un. un (a -> a bool) = g; f")
+        );
+
+        // エラーは別名を含む式の位置を持つ
+        let src = "type B = un bool;\nun fn x : C { x }";
+        let (_, mut expr) = parser::parse_expr(src).unwrap();
+        let err = resolve(&mut expr).unwrap_err();
+        assert_eq!(
+            "at line 2, column 1: unknown type \"C\"",
+            err.render(src).lines().next().unwrap()
+        );
+    }
+}
@@ -0,0 +1,335 @@
+//! 型付けと評価のエラー
+//!
+//! エラーの種類とエラーが発生した式の位置を保持し、
+//! ソースコードの該当箇所を示して表示する。
+
+use crate::parser::{BinOp, Qual, Span, TypeExpr};
+use std::fmt;
+
+/// 位置情報付きのエラー
+///
+/// エラーの種類は型を含み大きいため、Resultが大きくならないようBoxで保持する
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub kind: Box<ErrorKind>,
+    pub span: Option<Span>,
+}
+
+/// エラーの種類
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    Syntax(String), // 構文エラー
+
+    // 型付けエラー
+    UnboundVariable(String), // 定義されていないか、キャプチャできない変数
+    AlreadyUsed(String),     // 消費済みのlin, aff型の変数
+    LinearNotConsumed {
+        var: String,
+        scope: Scope,
+    }, // スコープ内で消費されなかったlin型の変数
+    CannotFree(String),      // 解放済みか、lin, aff型でない変数のfree
+    DuplicateVariable(String), // splitの変数名の重複
+    TypeMismatch {
+        var: String,
+        expected: TypeExpr,
+        found: TypeExpr,
+    }, // 束縛する値の型が注釈と異なる
+    ArgumentMismatch {
+        expected: TypeExpr,
+        found: TypeExpr,
+    }, // 引数の型が関数の引数の型と異なる
+    InjectionMismatch {
+        expected: TypeExpr,
+        found: TypeExpr,
+    }, // 注入する値の型が直和の型と異なる
+    NotAFunction(TypeExpr),  // 関数型でない値の適用
+    NotAPair(TypeExpr),      // ペア型でない値のsplit
+    NotASum(TypeExpr),       // 直和型でない値のcase
+    NotABool(TypeExpr),      // bool型でないif式の条件
+    NotAnInt {
+        op: BinOp,
+        found: TypeExpr,
+    }, // int型でない演算子の被演算子
+    NotDiscardable(TypeExpr), // 逐次実行で捨てられない値
+    LinearInUnPair,          // un型のペアの要素にlin, aff型
+    LinearInUnSum,           // un型の直和の要素にlin, aff型
+    BranchTypeMismatch(Branch), // 分岐の型が異なる
+    BranchEnvMismatch(Branch), // 分岐の評価後の型環境が異なる
+    RecNotFunction(String),  // 再帰的な束縛の値が関数でない
+    RecNotUn(String),        // 再帰関数の型がun型の関数型でない
+    RecNotAnnotated(String), // 再帰関数の型注釈がない
+    CaptureQualifier {
+        var: String,
+        ty: TypeExpr,
+        closure: Qual,
+    }, // 修飾子変数の型の変数を、その修飾子以上でない関数がキャプチャ
+    BoundViolation {
+        var: String,
+        bound: Qual,
+        found: TypeExpr,
+    }, // 型変数を修飾子の上限を超える型で具体化
+    NestingTooDeep,          // スコープのネストが深すぎる
+
+    // 型の別名のエラー
+    UnknownType(String),      // 定義されていない型の別名
+    RecursiveType(String),    // 自身の定義の中で自身を参照する型の別名
+    UnboundQualifier(String), // 量化されていない修飾子変数
+
+    // 評価エラー
+    VariableNotFound(String),  // 変数環境に値がない
+    CapturedCollected(String), // キャプチャした値が回収済み
+    NothingToFree,             // freeする変数がない
+    NotAClosure,               // クロージャでない値の適用
+    InvalidOperands(BinOp),    // 整数でない被演算子
+    NotABoolValue,             // 真偽値でないif式の条件
+    NotAPairValue,             // ペアでない値のsplit
+    NotASumValue,              // 直和でない値のcase
+    IntegerOverflow,           // 整数のオーバーフロー
+    DivisionByZero,            // ゼロ除算
+}
+
+/// lin型の変数を消費すべきスコープ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Function,
+    Let,
+    Split,
+    Case,
+}
+
+/// 型と型環境が一致すべき分岐
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Branch {
+    If,   // thenとelse
+    Case, // inlとinr
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ErrorKind::*;
+        match self {
+            Syntax(msg) => write!(f, "{msg}"),
+            UnboundVariable(v) | AlreadyUsed(v) => write!(
+                f,
+                "The variable \"{v}\" is either not defined, already used, or cannot be captured."
+            ),
+            LinearNotConsumed { var, scope } => match scope {
+                Scope::Function => write!(
+                    f,
+                    "the variable \"{var}\" of type lin is not consumed in the function definition."
+                ),
+                Scope::Let => write!(
+                    f,
+                    "lin type variable \"{var}\" does not consumed in let expression"
+                ),
+                Scope::Split => write!(
+                    f,
+                    "the variable \"{var}\" of type lin is not consumed in the expression of split."
+                ),
+                Scope::Case => write!(
+                    f,
+                    "the variable \"{var}\" of type lin is not consumed in the expression of case."
+                ),
+            },
+            CannotFree(v) => write!(
+                f,
+                "The variable \"{v}\" has already been freed or is not a lin or aff type."
+            ),
+            DuplicateVariable(_) => write!(f, "splitの変数名が同じ"),
+            TypeMismatch { var, .. } => {
+                write!(f, "The type of the variable \"{var}\" is different.")
+            }
+            ArgumentMismatch { .. } => {
+                write!(f, "different argument type when applying functions")
+            }
+            InjectionMismatch { .. } => write!(
+                f,
+                "the type of the injected value is different from the sum type"
+            ),
+            NotAFunction(_) => write!(f, "not a function type"),
+            NotAPair(_) => write!(f, "argument of split is not a pair type"),
+            NotASum(_) => write!(f, "argument of case is not a sum type"),
+            NotABool(_) => write!(f, "conditional expression in if-statement is not bool"),
+            NotAnInt { op, .. } => write!(f, "the operand of \"{op}\" is not int"),
+            NotDiscardable(t) => write!(
+                f,
+                "the value of type {t} cannot be discarded in sequence, expected un unit or aff"
+            ),
+            LinearInUnPair => write!(f, "using lin or aff type within a pair of un types"),
+            LinearInUnSum => write!(f, "using lin or aff type within a sum of un types"),
+            // 分岐後の型環境が異なる場合も、従来どおり型が異なるというメッセージとする
+            BranchTypeMismatch(b) | BranchEnvMismatch(b) => match b {
+                Branch::If => write!(
+                    f,
+                    "the types of then and else expressions in if statement are different."
+                ),
+                Branch::Case => write!(
+                    f,
+                    "the types of inl and inr expressions in case expression are different."
+                ),
+            },
+            RecNotFunction(v) => write!(
+                f,
+                "the value of the recursive binding \"{v}\" is not a function"
+            ),
+            RecNotUn(v) => write!(
+                f,
+                "the recursive function \"{v}\" must be of type un (... -> ...)"
+            ),
+            RecNotAnnotated(v) => {
+                write!(f, "the recursive function \"{v}\" needs a type annotation")
+            }
+            CaptureQualifier { var, ty, closure } => write!(
+                f,
+                "the {closure} function cannot capture the variable \"{var}\" of type {ty}"
+            ),
+            BoundViolation { var, bound, found } => write!(
+                f,
+                "the type variable \"{var}\" of bound {bound} cannot be instantiated with {found}"
+            ),
+            NestingTooDeep => write!(f, "variable scope nesting is too deep"),
+            UnknownType(name) => write!(f, "unknown type \"{name}\""),
+            UnboundQualifier(q) => {
+                write!(f, "the qualifier variable \"{q}\" is not bound by forall")
+            }
+            RecursiveType(name) => write!(f, "the type alias \"{name}\" refers to itself"),
+            VariableNotFound(v) => write!(f, "variable {v} not found"),
+            CapturedCollected(v) => write!(f, "captured variable {v} was collected"),
+            NothingToFree => write!(f, "no variable to free"),
+            NotAClosure => write!(f, "app expr should be closure"),
+            InvalidOperands(op) => write!(f, "the operands of \"{op}\" must be int"),
+            NotABoolValue => write!(
+                f,
+                "Conditional expression in if statements must be of type bool"
+            ),
+            NotAPairValue => write!(f, "The argument of split must be of type pair"),
+            NotASumValue => write!(f, "The argument of case must be of type sum"),
+            IntegerOverflow => write!(f, "integer overflow"),
+            DivisionByZero => write!(f, "division by zero"),
+        }
+    }
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, span: Span) -> Error {
+        Error {
+            kind: Box::new(kind),
+            span: Some(span),
+        }
+    }
+
+    /// 位置情報が未設定の場合のみspanを設定する
+    pub fn or_span(mut self, span: Span) -> Error {
+        if self.span.is_none() {
+            self.span = Some(span);
+        }
+        self
+    }
+
+    /// linzin::parseが返したエラーの見出し
+    ///
+    /// 型の別名や修飾子変数の解決に失敗した場合は、構文エラーと区別する
+    pub fn parse_label(&self) -> &'static str {
+        match *self.kind {
+            ErrorKind::UnknownType(_)
+            | ErrorKind::RecursiveType(_)
+            | ErrorKind::UnboundQualifier(_) => "resolution error",
+            _ => "parse error",
+        }
+    }
+
+    /// エラーメッセージと、ソースコードの該当箇所に下線を引いた文字列を返す
+    ///
+    /// ```text
+    /// at line 2, column 5: the variable "x" ...
+    ///     x
+    ///     ^
+    /// ```
+    pub fn render(&self, src: &str) -> String {
+        let span = match self.span {
+            Some(span) => span,
+            None => return format!("{}\n", self.kind),
+        };
+        let (line, col) = span.line_col(src);
+        let text = src.lines().nth(line - 1).unwrap_or("");
+
+        // 複数行にまたがる場合は、開始行の末尾まで下線を引く
+        let start = span.start.min(src.len());
+        let end = span.end.clamp(start, src.len());
+        let width = src[start..end]
+            .lines()
+            .next()
+            .map_or(0, |l| l.chars().count())
+            .max(1);
+
+        format!(
+            "at line {line}, column {col}: {}\n{text}\n{}{}\n",
+            self.kind,
+            " ".repeat(col - 1),
+            "^".repeat(width)
+        )
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error {
+            kind: Box::new(kind),
+            span: None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parser, typing};
+
+    #[test]
+    fn test_span() {
+        let input = "let x : un bool = un true;\n(f x)";
+        let (_, expr) = parser::parse(input).unwrap();
+        let body = match &expr {
+            parser::Expr::Let(e) => &e.expr2,
+            _ => panic!("let expression expected"),
+        };
+        assert_eq!(0, expr.span().start);
+        assert_eq!(input.len(), expr.span().end);
+        assert_eq!((2, 1), body.span().line_col(input));
+        assert_eq!("(f x)", &input[body.span().start..body.span().end]);
+    }
+
+    #[test]
+    fn test_render() {
+        let input = "let x : lin bool = lin true;\nlin <x, x>";
+        let (_, mut expr) = parser::parse(input).unwrap();
+        let e = typing::typing(&mut expr, &mut typing::TypeEnv::new(), 0).unwrap_err();
+        assert_eq!(
+            "at line 2, column 9: The variable \"x\" is either not defined, already used, or cannot be captured.\nlin <x, x>\n        ^\n",
+            e.render(input)
+        );
+    }
+
+    #[test]
+    fn test_render_with_comments() {
+        // コメントを読み飛ばしても行の構造は保たれる
+        let input = "// コメント
+let x : lin bool = /* 値 */ lin true; // xを束縛
+/* 複数行の
+   コメント */ lin <x, x>";
+        let (_, mut expr) = parser::parse(input).unwrap();
+        let e = typing::typing(&mut expr, &mut typing::TypeEnv::new(), 0).unwrap_err();
+        assert_eq!(
+            "at line 4, column 20: The variable \"x\" is either not defined, already used, or cannot be captured.\n   コメント */ lin <x, x>\n                   ^\n",
+            e.render(input)
+        );
+
+        // 閉じられていないコメント
+        assert!(parser::parse("lin true /* コメント").is_err());
+    }
+}
@@ -3,45 +3,161 @@
 //!
 
 use crate::{
-    helper::safe_add,
-    parser::{self},
+    error::{Error, ErrorKind},
+    gc::{self, GC},
+    helper::{safe_add, safe_div, safe_mul, safe_rem, safe_sub},
+    parser::{self, Qual},
     typing,
 };
-use std::{borrow::Cow, collections::BTreeMap, fmt};
+use std::{collections::BTreeMap, fmt};
 
 type VarToVal = BTreeMap<String, Option<ReturnVal>>;
 
-type VResult<'a> = Result<ReturnVal, Cow<'a, str>>;
+type VResult = Result<ReturnVal, Error>;
 
+/// 評価結果の値
+///
+/// 各値は、値を生成した式の修飾子を持つ。
+/// 演算の結果は、型付けと同様に被演算子の修飾子のうち最も制約の強いものとする
+///
+/// 型注釈などで型の修飾子が広がっても値の修飾子は変わらないため、
+/// `let x : lin bool = un true; x`の値は`un true`と表示される
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum ReturnVal {
-    Bool(bool),       // 真偽値リテラル
-    Pair(bool, bool), // ペア
-    Fun(Closure),     // 関数
+    Bool(Qual, bool),                           // 真偽値リテラル
+    Int(Qual, i64),                             // 整数
+    Unit(Qual),                                 // unit値
+    Pair(Qual, Box<ReturnVal>, Box<ReturnVal>), // ペア
+    Inl(Qual, Box<ReturnVal>),                  // 直和の左側
+    Inr(Qual, Box<ReturnVal>),                  // 直和の右側
+    Fun(Qual, Closure),                         // 関数
 }
 
+impl ReturnVal {
+    /// 値の修飾子
+    pub fn qual(&self) -> &Qual {
+        match self {
+            ReturnVal::Bool(q, _)
+            | ReturnVal::Int(q, _)
+            | ReturnVal::Unit(q)
+            | ReturnVal::Pair(q, _, _)
+            | ReturnVal::Inl(q, _)
+            | ReturnVal::Inr(q, _)
+            | ReturnVal::Fun(q, _) => q,
+        }
+    }
+
+    /// クロージャがキャプチャした変数の値も表示する
+    ///
+    /// キャプチャした値はenvのヒープから取り出す
+    ///
+    /// ```text
+    /// lin <closure fn y: un bool [x = lin true]>
+    /// ```
+    pub fn verbose<'a>(&'a self, env: &'a ValEnv) -> ValDisplay<'a> {
+        ValDisplay {
+            val: self,
+            heap: Some(&env.heap),
+        }
+    }
+}
+
+/// 値をLinzinの構文で表示
+///
+/// ```text
+/// lin <un true, lin false>
+/// lin <closure fn x: lin bool>
+/// ```
 impl fmt::Display for ReturnVal {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            ReturnVal::Bool(v) => write!(f, "{v}"),
-            ReturnVal::Pair(t1, t2) => write!(f, "({t1} , {t2})"),
-            ReturnVal::Fun(c) => write!(f, "{:?}", c),
+        ValDisplay {
+            val: self,
+            heap: None,
         }
+        .fmt(f)
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// 値の表示。heapがある場合は、クロージャがキャプチャした変数の値も表示する
+pub struct ValDisplay<'a> {
+    val: &'a ReturnVal,
+    heap: Option<&'a GC>,
+}
+
+impl fmt::Display for ValDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sub = |val| ValDisplay {
+            val,
+            heap: self.heap,
+        };
+        match self.val {
+            ReturnVal::Bool(q, v) => write!(f, "{q} {v}"),
+            ReturnVal::Int(q, v) => write!(f, "{q} {v}"),
+            ReturnVal::Unit(q) => write!(f, "{q} ()"),
+            ReturnVal::Pair(q, v1, v2) => write!(f, "{q} <{}, {}>", sub(v1), sub(v2)),
+            ReturnVal::Inl(q, v) => write!(f, "{q} inl {}", sub(v)),
+            ReturnVal::Inr(q, v) => write!(f, "{q} inr {}", sub(v)),
+            ReturnVal::Fun(q, c) => {
+                write!(f, "{q} <closure fn {}: {}", c.f.var, c.f.ty)?;
+                if let Some(heap) = self.heap {
+                    let captured: Vec<String> = c
+                        .env
+                        .iter()
+                        .map(|(k, addr)| match heap.get(*addr) {
+                            Some(v) => format!("{k} = {}", sub(v)),
+                            None => format!("{k} = <collected>"),
+                        })
+                        .collect();
+                    if !captured.is_empty() {
+                        write!(f, " [{}]", captured.join(", "))?;
+                    }
+                }
+                write!(f, ">")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
 pub struct ValEnv {
     env: ValEnvStack,
+    heap: GC, // クロージャがキャプチャした値を置くヒープ
 }
 
 impl ValEnv {
     pub fn new() -> ValEnv {
         ValEnv {
             env: ValEnvStack::new(),
+            heap: GC::new(),
         }
     }
 
+    /// ヒープの統計情報
+    pub fn heap_stats(&self) -> gc::Stats {
+        self.heap.stats()
+    }
+
+    /// 生存しているオブジェクト数が閾値に達していれば、GCを実行
+    fn maybe_collect_garbage(&mut self, extra: &[&ReturnVal]) {
+        if self.heap.needs_collect() {
+            self.collect_garbage(extra);
+        }
+    }
+
+    /// 変数環境とextraをルートとしてGCを実行
+    fn collect_garbage(&mut self, extra: &[&ReturnVal]) {
+        let mut roots = Vec::new();
+        for vars in self.env.vars.values() {
+            for v in vars.values().flatten() {
+                gc::refs(v, &mut roots);
+            }
+        }
+        for v in extra {
+            gc::refs(v, &mut roots);
+        }
+        self.heap.collect(roots);
+    }
+
     /// 変数環境をpush
     pub fn push(&mut self, depth: usize) {
         self.env.push(depth);
@@ -49,8 +165,7 @@ impl ValEnv {
 
     /// 変数環境をpop
     fn pop(&mut self, depth: usize) -> Option<VarToVal> {
-        let v = self.env.pop(depth);
-        v
+        self.env.pop(depth)
     }
 
     /// 変数環境へ変数と値をpush
@@ -58,6 +173,11 @@ impl ValEnv {
         self.env.insert(key, value);
     }
 
+    /// 変数の値を取得。未定義か消費済みの場合はNone
+    pub fn get(&self, key: &str) -> Option<&ReturnVal> {
+        self.env.get(key).and_then(|(_, v)| v.as_ref())
+    }
+
     fn get_mut(&mut self, key: &str) -> Option<&mut Option<ReturnVal>> {
         if let Some((_, t)) = self.env.get_mut(key) {
             Some(t)
@@ -105,6 +225,16 @@ impl ValEnvStack {
     }
 
     // スタックを上から辿っていき, 初めに見つかる変数の値を取得
+    fn get(&self, key: &str) -> Option<(usize, &Option<ReturnVal>)> {
+        for (depth, elm) in self.vars.iter().rev() {
+            if let Some(e) = elm.get(key) {
+                return Some((*depth, e));
+            }
+        }
+        None
+    }
+
+    // getの可変版
     fn get_mut(&mut self, key: &str) -> Option<(usize, &mut Option<ReturnVal>)> {
         for (depth, elm) in self.vars.iter_mut().rev() {
             if let Some(e) = elm.get_mut(key) {
@@ -127,121 +257,189 @@ impl ValEnvStack {
     }
 }
 
+/// クロージャ
+///
+/// キャプチャした変数の値はヒープ上にあり、変数名とアドレスの対応のみを保持する
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Closure {
     pub(crate) f: parser::FnExpr,
-    pub(crate) env: ValEnv,
+    pub(crate) env: BTreeMap<String, usize>,
+    pub(crate) rec: Option<String>, // 再帰関数の場合は自身の名前
 }
 
 impl Closure {
-    pub fn new(f: parser::FnExpr, env: ValEnv) -> Closure {
-        Closure { f: f, env }
+    pub fn new(f: parser::FnExpr, env: BTreeMap<String, usize>, rec: Option<String>) -> Closure {
+        Closure { f, env, rec }
     }
 }
 
-pub fn eval<'a>(
+pub fn eval(
     expr: &parser::Expr,
     type_env: &mut typing::TypeEnv,
     val_env: &mut ValEnv,
     depth: usize,
-) -> VResult<'a> {
-    match expr {
+) -> VResult {
+    let ret = match expr {
         parser::Expr::App(e) => eval_app(e, type_env, val_env, depth),
+        parser::Expr::BinOp(e) => eval_binop(e, type_env, val_env, depth),
         parser::Expr::QVal(e) => eval_qval(e, type_env, val_env, depth),
         parser::Expr::Free(e) => eval_free(e, type_env, val_env, depth),
         parser::Expr::If(e) => eval_if(e, type_env, val_env, depth),
         parser::Expr::Split(e) => eval_split(e, type_env, val_env, depth),
-        parser::Expr::Var(e) => eval_var(e, type_env, val_env),
+        parser::Expr::Case(e) => eval_case(e, type_env, val_env, depth),
+        parser::Expr::Var(e) => eval_var(&e.var, type_env, val_env),
         parser::Expr::Let(e) => eval_let(e, type_env, val_env, depth),
         parser::Expr::Def(e) => eval_def(e, type_env, val_env, depth),
         parser::Expr::Env(e) => eval_env(e, type_env, val_env, depth),
-    }
+        parser::Expr::Type(e) => eval(&e.expr, type_env, val_env, depth),
+        parser::Expr::Seq(e) => eval_seq(e, type_env, val_env, depth),
+    };
+    ret.map_err(|e| e.or_span(expr.span()))
 }
 
-fn eval_app<'a>(
+fn eval_app(
     expr: &parser::AppExpr,
     type_env: &mut typing::TypeEnv,
     val_env: &mut ValEnv,
     depth: usize,
-) -> VResult<'a> {
+) -> VResult {
     let f = eval(&expr.expr1, type_env, val_env, depth)?;
 
-    let arg = eval(&expr.expr2, type_env, val_env, depth)?;
+    // 引数の評価中に関数が回収されないようルートに追加
+    let mark = val_env.heap.push_root(&f);
+    let arg = eval(&expr.expr2, type_env, val_env, depth);
+    val_env.heap.pop_roots(mark);
+    let arg = arg?;
+
     match f {
-        ReturnVal::Fun(c) => {
+        ReturnVal::Fun(q, c) => {
+            // キャプチャした変数の値をヒープから取り出す。
+            // 回収済みの場合はスコープを作る前にエラーとする
+            let mut captured = Vec::new();
+            for (k, addr) in c.env.iter() {
+                match val_env.heap.get(*addr) {
+                    Some(v) => captured.push((k.clone(), v.clone())),
+                    None => return Err(ErrorKind::CapturedCollected(k.clone()).into()),
+                }
+            }
             let mut depth = depth;
-            safe_add(&mut depth, &1, || "Variable scope nesting is too deep")?;
+            safe_add(&mut depth, &1, || ErrorKind::NestingTooDeep)?;
             val_env.push(depth);
-            // insert closure env to val_env with keeping the order
-            for (_, v) in c.env.clone().env.vars.into_iter().rev() {
-                for (k2, v2) in v.into_iter() {
-                    val_env.insert(k2, v2.unwrap());
-                }
+            for (k, v) in captured {
+                val_env.insert(k, v);
+            }
+            // 再帰関数は自身を束縛
+            if let Some(name) = &c.rec {
+                val_env.insert(name.clone(), ReturnVal::Fun(q, c.clone()));
             }
-            val_env.insert(c.f.var.clone(), arg.clone());
+            val_env.insert(c.f.var.clone(), arg);
             type_env.push(depth);
+            type_env.insert(c.f.var.clone(), c.f.ty.clone());
             let e = eval(&c.f.expr, type_env, val_env, depth);
             val_env.pop(depth);
             type_env.pop(depth);
-            //dbg!("closure env",c);
+            if let Ok(v) = &e {
+                val_env.maybe_collect_garbage(&[v]);
+            }
             e
         }
-        _ => Err("app expr should be closure".into()),
+        _ => Err(ErrorKind::NotAClosure.into()),
     }
 }
 
-fn eval_qval<'a>(
+fn eval_binop(
+    expr: &parser::BinOpExpr,
+    type_env: &mut typing::TypeEnv,
+    val_env: &mut ValEnv,
+    depth: usize,
+) -> VResult {
+    let v1 = eval(&expr.expr1, type_env, val_env, depth)?;
+    let v2 = eval(&expr.expr2, type_env, val_env, depth)?;
+    let (q, mut n, m) = match (v1, v2) {
+        (ReturnVal::Int(q1, n), ReturnVal::Int(q2, m)) => (q1.join(&q2), n, m),
+        _ => return Err(ErrorKind::InvalidOperands(expr.op).into()),
+    };
+
+    let overflow = || ErrorKind::IntegerOverflow;
+    match expr.op {
+        parser::BinOp::Add => safe_add(&mut n, &m, overflow)?,
+        parser::BinOp::Sub => safe_sub(&mut n, &m, overflow)?,
+        parser::BinOp::Mul => safe_mul(&mut n, &m, overflow)?,
+        parser::BinOp::Div | parser::BinOp::Mod if m == 0 => {
+            return Err(ErrorKind::DivisionByZero.into())
+        }
+        parser::BinOp::Div => safe_div(&mut n, &m, overflow)?,
+        parser::BinOp::Mod => safe_rem(&mut n, &m, overflow)?,
+        parser::BinOp::Lt => return Ok(ReturnVal::Bool(q, n < m)),
+        parser::BinOp::Le => return Ok(ReturnVal::Bool(q, n <= m)),
+        parser::BinOp::Eq => return Ok(ReturnVal::Bool(q, n == m)),
+        parser::BinOp::Ne => return Ok(ReturnVal::Bool(q, n != m)),
+    }
+    Ok(ReturnVal::Int(q, n))
+}
+
+fn eval_qval(
     expr: &parser::QValExpr,
     type_env: &mut typing::TypeEnv,
     val_env: &mut ValEnv,
     depth: usize,
-) -> VResult<'a> {
-    let p = match &expr.val {
-        parser::ValExpr::Bool(v) => Ok(ReturnVal::Bool(*v)),
+) -> VResult {
+    let q = expr.qual.clone();
+    match &expr.val {
+        parser::ValExpr::Bool(v) => Ok(ReturnVal::Bool(q, *v)),
+        parser::ValExpr::Int(v) => Ok(ReturnVal::Int(q, *v)),
+        parser::ValExpr::Unit => Ok(ReturnVal::Unit(q)),
         parser::ValExpr::Pair(e1, e2) => {
             let v1 = eval(e1, type_env, val_env, depth)?;
-            let v2 = eval(e2, type_env, val_env, depth)?;
-            match (v1, v2) {
-                (ReturnVal::Bool(v1), ReturnVal::Bool(v2)) => Ok(ReturnVal::Pair(v1, v2)),
-                _ => Err("pair values should be bool".into()),
-            }
+            // 二つめの値の評価中に一つめの値が回収されないようルートに追加
+            let mark = val_env.heap.push_root(&v1);
+            let v2 = eval(e2, type_env, val_env, depth);
+            val_env.heap.pop_roots(mark);
+            Ok(ReturnVal::Pair(q, Box::new(v1), Box::new(v2?)))
+        }
+        parser::ValExpr::Inl(e) => {
+            let v = eval(&e.expr, type_env, val_env, depth)?;
+            Ok(ReturnVal::Inl(q, Box::new(v)))
+        }
+        parser::ValExpr::Inr(e) => {
+            let v = eval(&e.expr, type_env, val_env, depth)?;
+            Ok(ReturnVal::Inr(q, Box::new(v)))
         }
         // 使用する時までASTを保持しておく
         parser::ValExpr::Fun(e) => {
             let mut depth = depth;
-            safe_add(&mut depth, &1, || "Variable scope nesting is too deep")?;
+            safe_add(&mut depth, &1, || ErrorKind::NestingTooDeep)?;
             val_env.push(depth);
             type_env.push(depth);
-            let f = eval_fun(e, type_env, val_env, depth)?;
+            let f = eval_fun(e, q, None, type_env, val_env, depth)?;
             val_env.pop(depth);
             type_env.pop(depth);
             Ok(f)
         }
-    };
-    p
+    }
 }
-fn eval_free<'a>(
+fn eval_free(
     expr: &parser::FreeExpr,
     type_env: &mut typing::TypeEnv,
     val_env: &mut ValEnv,
     depth: usize,
-) -> VResult<'a> {
-    if let Some(_) = val_env.get_mut(&expr.var) {
+) -> VResult {
+    if val_env.get_mut(&expr.var).is_some() {
         let _ = val_env.remove(&expr.var);
         eval(&expr.expr, type_env, val_env, depth)
     } else {
-        Err("no variable to free".into())
+        Err(ErrorKind::NothingToFree.into())
     }
 }
-fn eval_if<'a>(
+fn eval_if(
     expr: &parser::IfExpr,
     type_env: &mut typing::TypeEnv,
     val_env: &mut ValEnv,
     depth: usize,
-) -> VResult<'a> {
-    let e1 = match eval(&expr.cond_expr, type_env, val_env, depth) {
-        Ok(ReturnVal::Bool(v)) => v,
-        _ => panic!("Conditional expression in if statements must be of type bool"),
+) -> VResult {
+    let e1 = match eval(&expr.cond_expr, type_env, val_env, depth)? {
+        ReturnVal::Bool(_, v) => v,
+        _ => return Err(ErrorKind::NotABoolValue.into()),
     };
     if e1 {
         eval(&expr.then_expr, type_env, val_env, depth)
@@ -250,120 +448,211 @@ fn eval_if<'a>(
     }
 }
 
-fn eval_split<'a>(
+fn eval_split(
     expr: &parser::SplitExpr,
     type_env: &mut typing::TypeEnv,
     val_env: &mut ValEnv,
     depth: usize,
-) -> VResult<'a> {
+) -> VResult {
     let e = eval(&expr.expr, type_env, val_env, depth)?;
     let mut depth = depth;
-    safe_add(&mut depth, &1, || "Variable scope nesting is too deep")?;
+    safe_add(&mut depth, &1, || ErrorKind::NestingTooDeep)?;
     match e {
-        ReturnVal::Pair(v1, v2) => {
+        ReturnVal::Pair(_, v1, v2) => {
             val_env.push(depth);
-            val_env.insert(expr.left.clone(), ReturnVal::Bool(v1));
-            val_env.insert(expr.right.clone(), ReturnVal::Bool(v2));
+            val_env.insert(expr.left.clone(), *v1);
+            val_env.insert(expr.right.clone(), *v2);
             type_env.push(depth);
+            // 型付けで記録した変数の型を追加
+            if let Some((t1, t2)) = &expr.tys {
+                type_env.insert(expr.left.clone(), t1.clone());
+                type_env.insert(expr.right.clone(), t2.clone());
+            }
         }
-        _ => panic!("The argument of split must be of type pair"),
+        _ => return Err(ErrorKind::NotAPairValue.into()),
     }
     let ret = eval(&expr.body, type_env, val_env, depth);
     let _ = val_env.pop(depth);
     let _ = type_env.pop(depth);
+    if let Ok(v) = &ret {
+        val_env.maybe_collect_garbage(&[v]);
+    }
 
     ret
 }
-fn eval_var<'a>(expr: &str, type_env: &mut typing::TypeEnv, val_env: &mut ValEnv) -> VResult<'a> {
-    let mut binding = val_env.clone();
-    let val = binding.get_mut(expr);
-    let val = match val {
-        Some(v) => v,
-        None => return Err(format!("variable {expr} not found").into()),
+fn eval_case(
+    expr: &parser::CaseExpr,
+    type_env: &mut typing::TypeEnv,
+    val_env: &mut ValEnv,
+    depth: usize,
+) -> VResult {
+    let e = eval(&expr.expr, type_env, val_env, depth)?;
+    let mut depth = depth;
+    safe_add(&mut depth, &1, || ErrorKind::NestingTooDeep)?;
+    // 型付けで記録した変数の型
+    let (var, v, body, t) = match e {
+        ReturnVal::Inl(_, v) => (
+            &expr.inl_var,
+            *v,
+            &expr.inl_expr,
+            expr.tys.as_ref().map(|(t, _)| t),
+        ),
+        ReturnVal::Inr(_, v) => (
+            &expr.inr_var,
+            *v,
+            &expr.inr_expr,
+            expr.tys.as_ref().map(|(_, t)| t),
+        ),
+        _ => return Err(ErrorKind::NotASumValue.into()),
     };
-    let ret = val.clone();
-    // もし変数がlinなら, 使用後freeする.
-    match type_env.env_lin.get_mut(expr) {
-        Some(_) => {
-            let _ = val_env.remove(expr);
-        }
-        None => (),
+    val_env.push(depth);
+    val_env.insert(var.clone(), v);
+    type_env.push(depth);
+    if let Some(t) = t {
+        type_env.insert(var.clone(), t.clone());
     }
-    match type_env.env_aff.get_mut(expr) {
-        Some(_) => {
-            let _ = val_env.remove(expr);
-        }
-        None => (),
+
+    let ret = eval(body, type_env, val_env, depth);
+    let _ = val_env.pop(depth);
+    let _ = type_env.pop(depth);
+    if let Ok(v) = &ret {
+        val_env.maybe_collect_garbage(&[v]);
     }
-    ret.ok_or(format!("variable {expr} not found").into())
+
+    ret
+}
+
+fn eval_var(expr: &str, type_env: &mut typing::TypeEnv, val_env: &mut ValEnv) -> VResult {
+    let ret = match val_env.get_mut(expr) {
+        Some(v) => v.clone(),
+        None => return Err(ErrorKind::VariableNotFound(expr.to_string()).into()),
+    };
+    // もし変数がlinかaffなら, 使用後freeする.
+    if is_linear(type_env, expr) {
+        let _ = val_env.remove(expr);
+    }
+    ret.ok_or_else(|| ErrorKind::VariableNotFound(expr.to_string()).into())
+}
+
+/// 変数がlinかaffの型環境にあるか
+fn is_linear(type_env: &mut typing::TypeEnv, key: &str) -> bool {
+    type_env.env_lin.get_mut(key).is_some() || type_env.env_aff.get_mut(key).is_some()
 }
 
-fn eval_let<'a>(
+fn eval_let(
     expr: &parser::LetExpr,
     type_env: &mut typing::TypeEnv,
     val_env: &mut ValEnv,
     depth: usize,
-) -> VResult<'a> {
-    let t = typing::typing(&expr.expr1, type_env, depth)?;
-    let v1 = match eval(&expr.expr1, type_env, val_env, depth) {
-        Ok(v) => v,
-        Err(e) => return Err(e),
+) -> VResult {
+    let (t, v1) = if expr.rec {
+        // 再帰関数は自身をキャプチャせず、適用時にeval_appで束縛する
+        let (q, f) = match &*expr.expr1 {
+            parser::Expr::QVal(parser::QValExpr {
+                qual,
+                val: parser::ValExpr::Fun(f),
+                ..
+            }) => (qual, f),
+            _ => return Err(ErrorKind::RecNotFunction(expr.var.clone()).into()),
+        };
+        let t = match &expr.ty {
+            Some(t) => t.clone(),
+            None => return Err(ErrorKind::RecNotAnnotated(expr.var.clone()).into()),
+        };
+        let v1 = eval_fun(f, q.clone(), Some(&expr.var), type_env, val_env, depth)?;
+        (Some(t), v1)
+    } else {
+        // 型付けと同様に、型注釈がある場合は型注釈の型を、ない場合は型付けで求めた型を変数の型とする
+        let t = expr.ty.clone().or_else(|| expr.inferred.clone());
+        let v1 = eval(&expr.expr1, type_env, val_env, depth)?;
+        (t, v1)
     };
     let mut depth = depth;
-    safe_add(&mut depth, &1, || "Variable scope nesting is too deep").unwrap();
+    safe_add(&mut depth, &1, || ErrorKind::NestingTooDeep)?;
     val_env.push(depth);
     val_env.insert(expr.var.clone(), v1);
     type_env.push(depth);
-    type_env.insert(expr.var.clone(), t);
+    if let Some(t) = t {
+        type_env.insert(expr.var.clone(), t);
+    }
 
     let v2 = eval(&expr.expr2, type_env, val_env, depth);
     _ = val_env.pop(depth);
     _ = type_env.pop(depth);
+    if let Ok(v) = &v2 {
+        val_env.maybe_collect_garbage(&[v]);
+    }
 
     v2
 }
 
-fn eval_def<'a>(
+fn eval_def(
     expr: &parser::DefExpr,
     type_env: &mut typing::TypeEnv,
     val_env: &mut ValEnv,
     depth: usize,
-) -> VResult<'a> {
-    let v1 = match eval(&expr.expr, type_env, val_env, depth) {
-        Ok(v) => v,
-        Err(e) => return Err(e),
-    };
+) -> VResult {
+    let v1 = eval(&expr.expr, type_env, val_env, depth)?;
     val_env.insert(expr.var.clone(), v1.clone());
     Ok(v1)
 }
 
-fn eval_env<'a>(
+fn eval_env(
     expr: &parser::EnvExpr,
     type_env: &mut typing::TypeEnv,
     val_env: &mut ValEnv,
     depth: usize,
-) -> VResult<'a> {
+) -> VResult {
     println!("[Type Environment]:\n {:#?}", type_env);
     println!("[Variable Environment]\n {:#?}", val_env);
-    let v = match eval(&expr.expr, type_env, val_env, depth) {
-        Ok(v) => v,
-        Err(e) => return Err(e),
-    };
+    let v = eval(&expr.expr, type_env, val_env, depth)?;
     Ok(v)
 }
 
-fn eval_fun<'a>(
+fn eval_seq(
+    expr: &parser::SeqExpr,
+    type_env: &mut typing::TypeEnv,
+    val_env: &mut ValEnv,
+    depth: usize,
+) -> VResult {
+    // 一つめの式の値は捨てる
+    eval(&expr.expr1, type_env, val_env, depth)?;
+    eval(&expr.expr2, type_env, val_env, depth)
+}
+
+/// クロージャを作成。qは関数の修飾子、recは再帰関数の場合の自身の名前
+fn eval_fun(
     expr: &parser::FnExpr,
-    _type_env: &mut typing::TypeEnv,
+    q: Qual,
+    rec: Option<&str>,
+    type_env: &mut typing::TypeEnv,
     val_env: &mut ValEnv,
     _depth: usize,
-) -> VResult<'a> {
-    // check if function use free variables
-    let env = val_env.clone();
-    return Ok(ReturnVal::Fun(Closure::new(expr.clone(), env)));
+) -> VResult {
+    // 関数本体の自由変数のみをヒープに確保してキャプチャ
+    // lin, affの変数はクロージャへ移動し、元の環境からは取り除く
+    let mut env = BTreeMap::new();
+    for k in expr.free_vars() {
+        if Some(k.as_str()) == rec {
+            continue;
+        }
+        let v = if is_linear(type_env, &k) {
+            val_env.remove(&k).flatten()
+        } else {
+            val_env.get_mut(&k).and_then(|v| v.clone())
+        };
+        if let Some(v) = v {
+            env.insert(k, val_env.heap.alloc(v));
+        }
+    }
+    Ok(ReturnVal::Fun(
+        q,
+        Closure::new(expr.clone(), env, rec.map(|r| r.to_string())),
+    ))
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use crate::eval::*;
     use crate::{
@@ -376,9 +665,10 @@ mod tests {
         let expr = QVal(QValExpr {
             qual: Qual::Un,
             val: ValExpr::Bool(true),
+            span: Span::default(),
         });
         let result = match eval(&expr, &mut typing::TypeEnv::new(), &mut ValEnv::new(), 0) {
-            Ok(ReturnVal::Bool(v)) => v,
+            Ok(ReturnVal::Bool(_, v)) => v,
             _ => panic!("error happend in eval_var test"),
         };
         assert_eq!(true, result);
@@ -394,7 +684,7 @@ mod tests {
             ";
         if let Ok((_, expr)) = parser::parse_expr(input) {
             let result = match eval(&expr, &mut typing::TypeEnv::new(), &mut ValEnv::new(), 0) {
-                Ok(ReturnVal::Bool(v)) => v,
+                Ok(ReturnVal::Bool(_, v)) => v,
                 _ => panic!("error happend in eval_if test"),
             };
             assert_eq!(false, result);
@@ -416,7 +706,7 @@ mod tests {
         (z  lin <lin true, lin false>)";
         if let Ok((_, expr)) = parser::parse_expr(input) {
             let result = match eval(&expr, &mut typing::TypeEnv::new(), &mut ValEnv::new(), 0) {
-                Ok(ReturnVal::Bool(v)) => v,
+                Ok(ReturnVal::Bool(_, v)) => v,
                 _ => panic!("error happend in eval_app test"),
             };
             assert_eq!(false, result);
@@ -424,4 +714,363 @@ mod tests {
         }
         unreachable!();
     }
+    #[test]
+    fn test_eval_nested_pair() {
+        let input = r"split lin <lin <lin true, lin false>, lin fn x : lin bool { x }> as p, f {
+            split p as a, b {
+                free a;
+                (f b)
+            }
+        }";
+        if let Ok((_, expr)) = parser::parse_expr(input) {
+            let result = match eval(&expr, &mut typing::TypeEnv::new(), &mut ValEnv::new(), 0) {
+                Ok(ReturnVal::Bool(_, v)) => v,
+                _ => panic!("error happend in eval_nested_pair test"),
+            };
+            assert!(!result);
+            return;
+        }
+        unreachable!();
+    }
+    #[test]
+    fn test_gc_collect_captured() {
+        // 捕獲されたxは, クロージャvを適用した後に回収される
+        let input = r"let t : lin (lin bool -> lin (lin bool -> un bool)) = lin fn u : lin bool {
+            free u;
+            let x : un bool = un true;
+            lin fn y : lin bool {
+                free y;
+                x
+            }
+        };
+        let v : lin (lin bool -> un bool) = (t lin true);
+        let s : un bool = (v lin false);
+        s";
+        let (_, expr) = parser::parse_expr(input).unwrap();
+        // 確保のたびにGCを実行する
+        let mut val_env = ValEnv {
+            heap: GC::with_threshold(1),
+            ..ValEnv::new()
+        };
+        let result = eval(&expr, &mut typing::TypeEnv::new(), &mut val_env, 0);
+        assert_eq!(Ok(ReturnVal::Bool(Qual::Un, true)), result);
+        assert!(val_env.heap_stats().collections > 0);
+
+        // vの適用後、xはどこからも到達できない
+        val_env.collect_garbage(&[]);
+        let stats = val_env.heap_stats();
+        assert!(stats.allocated > 0);
+        assert_eq!(0, stats.live_objects);
+        assert_eq!(stats.allocated, stats.freed);
+    }
+    #[test]
+    fn test_gc_keep_reachable() {
+        // グローバルに定義したクロージャがキャプチャした値は回収されない
+        let mut type_env = typing::TypeEnv::new();
+        // 確保のたびにGCを実行する
+        let mut val_env = ValEnv {
+            heap: GC::with_threshold(1),
+            ..ValEnv::new()
+        };
+        type_env.push(0);
+        val_env.push(0);
+        for input in [
+            "def x : un bool = un true;",
+            "def f : un (un bool -> un (un bool * un bool)) = un fn y : un bool { un <x, y> };",
+            "let z : un bool = un false; (f z)",
+        ] {
+            let (_, expr) = parser::parse(input).unwrap();
+            eval(&expr, &mut type_env, &mut val_env, 0).unwrap();
+        }
+        let stats = val_env.heap_stats();
+        assert!(stats.collections > 0);
+        assert_eq!(1, stats.live_objects);
+    }
+    #[test]
+    fn test_captured_collected() {
+        // キャプチャした値が回収済みの場合はエラーとし、スコープを残さない
+        let mut type_env = typing::TypeEnv::new();
+        let mut val_env = ValEnv::new();
+        type_env.push(0);
+        val_env.push(0);
+        let (_, expr) = parser::parse(
+            "def f : un (un bool -> un bool) = let x : un bool = un true; un fn y : un bool { x };",
+        )
+        .unwrap();
+        eval(&expr, &mut type_env, &mut val_env, 0).unwrap();
+        val_env.heap.collect(Vec::new());
+
+        let (_, expr) = parser::parse("(f un false)").unwrap();
+        let result = eval(&expr, &mut type_env, &mut val_env, 0);
+        assert_eq!(
+            ErrorKind::CapturedCollected("x".to_string()),
+            *result.unwrap_err().kind
+        );
+        assert_eq!(1, val_env.env.vars.len());
+        assert!(type_env.get("y").is_none());
+    }
+    #[test]
+    fn test_capture_free_vars() {
+        // クロージャは本体で使われる変数のみをキャプチャする
+        let input = r"let x : un bool = un true;
+        let w : un bool = un false;
+        un fn y : un bool { un <x, y> }";
+        let (_, expr) = parser::parse_expr(input).unwrap();
+        let result = eval(&expr, &mut typing::TypeEnv::new(), &mut ValEnv::new(), 0);
+        match result {
+            Ok(ReturnVal::Fun(_, c)) => assert_eq!(vec!["x"], c.env.keys().collect::<Vec<_>>()),
+            _ => panic!("error happend in capture_free_vars test"),
+        }
+    }
+    #[test]
+    fn test_capture_moves_lin() {
+        // lin型の変数はキャプチャ時にクロージャへ移動する
+        let input = r"let x : lin bool = lin true;
+        lin <lin fn y : un bool { x }, x>";
+        let (_, expr) = parser::parse_expr(input).unwrap();
+        let result = eval(&expr, &mut typing::TypeEnv::new(), &mut ValEnv::new(), 0);
+        assert_eq!(
+            ErrorKind::VariableNotFound("x".to_string()),
+            *result.unwrap_err().kind
+        );
+
+        let input = r"let x : un bool = un true;
+        un <un fn y : un bool { x }, x>";
+        let (_, expr) = parser::parse_expr(input).unwrap();
+        let result = eval(&expr, &mut typing::TypeEnv::new(), &mut ValEnv::new(), 0);
+        assert!(result.is_ok());
+    }
+    #[test]
+    fn test_split_case_consume_lin() {
+        // splitやcaseで束縛したlinの値は、型付けで記録した型により使用後に取り除かれる。
+        // 型付けした式の本体を、変数を二度使う式に置き換えて評価する
+        let body = |input: &str| Box::new(parser::parse_expr(input).unwrap().1);
+        let (_, mut expr) =
+            parser::parse_expr("split lin <lin true, un false> as a, b { lin <a, b> }").unwrap();
+        typing::typing(&mut expr, &mut typing::TypeEnv::new(), 0).unwrap();
+        if let Split(e) = &mut expr {
+            e.body = body("lin <lin <a, b>, a>");
+        }
+        let result = eval(&expr, &mut typing::TypeEnv::new(), &mut ValEnv::new(), 0);
+        assert_eq!(
+            ErrorKind::VariableNotFound("a".to_string()),
+            *result.unwrap_err().kind
+        );
+        // unの値は何度でも使える
+        if let Split(e) = &mut expr {
+            e.body = body("lin <a, un <b, b>>");
+        }
+        let result = eval(&expr, &mut typing::TypeEnv::new(), &mut ValEnv::new(), 0);
+        assert!(result.is_ok());
+
+        let (_, mut expr) = parser::parse_expr(
+            "case lin inl (lin bool + un bool) lin true of inl a { a } | inr b { lin true }",
+        )
+        .unwrap();
+        typing::typing(&mut expr, &mut typing::TypeEnv::new(), 0).unwrap();
+        if let Case(e) = &mut expr {
+            e.inl_expr = body("lin <a, a>");
+        }
+        let result = eval(&expr, &mut typing::TypeEnv::new(), &mut ValEnv::new(), 0);
+        assert_eq!(
+            ErrorKind::VariableNotFound("a".to_string()),
+            *result.unwrap_err().kind
+        );
+    }
+    #[test]
+    fn test_eval_let_infer() {
+        // 型注釈のないletは型付けで求めた型を用い、評価時には型付けしない。
+        // スコープの外で適用したクロージャの本体でも評価できる
+        let input = r"let mk = un fn x : un bool { un fn y : un bool { let z = x; z } };
+        let f = (mk un true);
+        (f un false)";
+        let (_, mut expr) = parser::parse_expr(input).unwrap();
+        typing::typing(&mut expr, &mut typing::TypeEnv::new(), 0).unwrap();
+        let result = eval(&expr, &mut typing::TypeEnv::new(), &mut ValEnv::new(), 0);
+        assert_eq!(Ok(ReturnVal::Bool(Qual::Un, true)), result);
+    }
+    #[test]
+    fn test_eval_int() {
+        let input = r"let x : un int = un 7;
+        let y : un int = ((x * un 3) - (x % un 4));
+        un <(y / un 2), (y <= x)>";
+        let (_, expr) = parser::parse_expr(input).unwrap();
+        let result = eval(&expr, &mut typing::TypeEnv::new(), &mut ValEnv::new(), 0);
+        assert_eq!(
+            Ok(ReturnVal::Pair(
+                Qual::Un,
+                Box::new(ReturnVal::Int(Qual::Un, 9)),
+                Box::new(ReturnVal::Bool(Qual::Un, false))
+            )),
+            result
+        );
+    }
+    #[test]
+    fn test_eval_multi_arg() {
+        let input = r"let f : un (un int -> un (un int -> un (un int -> un int))) = un fn x : un int, y : un int, z : un int {
+            ((x - y) * z)
+        };
+        let g : un (un int -> un int) = (f un 7 un 2);
+        un <(f un 1 un 2 un 3), (g un 4)>";
+        let mut expr = parser::parse_expr(input).unwrap().1;
+        crate::alias::resolve(&mut expr).unwrap();
+        let result = eval(&expr, &mut typing::TypeEnv::new(), &mut ValEnv::new(), 0);
+        assert_eq!(
+            Ok(ReturnVal::Pair(
+                Qual::Un,
+                Box::new(ReturnVal::Int(Qual::Un, -3)),
+                Box::new(ReturnVal::Int(Qual::Un, 20))
+            )),
+            result
+        );
+    }
+    #[test]
+    fn test_eval_qual_poly() {
+        let input = r"let id : forall q. un (q bool -> q bool) = un fn x : q bool { x };
+        let x = (id lin true);
+        lin <x, (id un false)>";
+        let (_, mut expr) = parser::parse_expr(input).unwrap();
+        typing::typing(&mut expr, &mut typing::TypeEnv::new(), 0).unwrap();
+        let result = eval(&expr, &mut typing::TypeEnv::new(), &mut ValEnv::new(), 0);
+        assert_eq!(
+            Ok(ReturnVal::Pair(
+                Qual::Lin,
+                Box::new(ReturnVal::Bool(Qual::Lin, true)),
+                Box::new(ReturnVal::Bool(Qual::Un, false))
+            )),
+            result
+        );
+    }
+
+    #[test]
+    fn test_eval_type_poly() {
+        let input = r"let swap : forall a : lin, b : lin. un (lin (a * b) -> lin (b * a)) =
+            un fn p : lin (a * b) { split p as x, y { lin <y, x> } };
+        (swap lin <un 1, lin true>)";
+        let mut expr = parser::parse_expr(input).unwrap().1;
+        crate::alias::resolve(&mut expr).unwrap();
+        typing::typing(&mut expr, &mut typing::TypeEnv::new(), 0).unwrap();
+        let result = eval(&expr, &mut typing::TypeEnv::new(), &mut ValEnv::new(), 0);
+        assert_eq!(
+            Ok(ReturnVal::Pair(
+                Qual::Lin,
+                Box::new(ReturnVal::Bool(Qual::Lin, true)),
+                Box::new(ReturnVal::Int(Qual::Un, 1))
+            )),
+            result
+        );
+    }
+
+    #[test]
+    fn test_eval_int_overflow() {
+        for (input, kind) in [
+            (
+                "(un 9223372036854775807 + un 1)",
+                ErrorKind::IntegerOverflow,
+            ),
+            (
+                "(un -9223372036854775807 - un 2)",
+                ErrorKind::IntegerOverflow,
+            ),
+            ("(un 1 / un 0)", ErrorKind::DivisionByZero),
+            ("(un 1 % un 0)", ErrorKind::DivisionByZero),
+        ] {
+            let (_, expr) = parser::parse(input).unwrap();
+            let result = eval(&expr, &mut typing::TypeEnv::new(), &mut ValEnv::new(), 0);
+            assert_eq!(kind, *result.unwrap_err().kind);
+        }
+    }
+    #[test]
+    fn test_eval_rec() {
+        let input = r"let rec fact : un (un int -> un int) = un fn n : un int {
+            if (n <= un 1) {
+                un 1
+            } else {
+                (n * (fact (n - un 1)))
+            }
+        };
+        (fact un 10)";
+        let (_, expr) = parser::parse_expr(input).unwrap();
+        let result = eval(&expr, &mut typing::TypeEnv::new(), &mut ValEnv::new(), 0);
+        assert_eq!(Ok(ReturnVal::Int(Qual::Un, 3628800)), result);
+    }
+    #[test]
+    fn test_eval_case() {
+        let input = r"case lin inr (lin bool + lin (lin int * lin int)) lin <lin 1, lin 2> of inl b {
+            b
+        } | inr p {
+            split p as x, y {
+                (x < y)
+            }
+        }";
+        let (_, expr) = parser::parse_expr(input).unwrap();
+        let result = eval(&expr, &mut typing::TypeEnv::new(), &mut ValEnv::new(), 0);
+        // 演算の結果は被演算子の修飾子を引き継ぐ
+        assert_eq!(Ok(ReturnVal::Bool(Qual::Lin, true)), result);
+    }
+    #[test]
+    fn test_eval_runtime_error() {
+        // 型付けされていない式の実行時エラーはpanicせずErrを返し、環境を保つ
+        let mut type_env = typing::TypeEnv::new();
+        let mut val_env = ValEnv::new();
+        type_env.push(0);
+        val_env.push(0);
+        let (_, expr) = parser::parse("def x : un bool = un true;").unwrap();
+        eval(&expr, &mut type_env, &mut val_env, 0).unwrap();
+
+        for (input, kind) in [
+            ("if un 1 { x } else { x }", ErrorKind::NotABoolValue),
+            ("split un true as a, b { a }", ErrorKind::NotAPairValue),
+            (
+                "let y : un bool = un 1; split y as a, b { a }",
+                ErrorKind::NotAPairValue,
+            ),
+        ] {
+            let (_, expr) = parser::parse(input).unwrap();
+            let result = eval(&expr, &mut type_env, &mut val_env, 0);
+            assert_eq!(kind, *result.unwrap_err().kind);
+        }
+
+        let (_, expr) = parser::parse("x").unwrap();
+        assert_eq!(
+            Ok(ReturnVal::Bool(Qual::Un, true)),
+            eval(&expr, &mut type_env, &mut val_env, 0)
+        );
+    }
+    #[test]
+    fn test_print_val() {
+        // 値は修飾子付きのLinzinの構文で表示する
+        for (input, output) in [
+            ("lin <un true, lin false>", "lin <un true, lin false>"),
+            ("(un 1 + lin 2)", "lin 3"),
+            (
+                "aff inr (un bool + un (un int * un unit)) un <un -1, ()>",
+                "aff inr un <un -1, un ()>",
+            ),
+            ("lin fn x : lin bool { x }", "lin <closure fn x: lin bool>"),
+            // 型注釈で修飾子を広げても、生成時の修飾子を表示する
+            ("let x : lin bool = un true; x", "un true"),
+            (
+                "let f : un (un bool -> lin bool) = un fn x : un bool { x }; (f un true)",
+                "un true",
+            ),
+        ] {
+            let (_, expr) = parser::parse(input).unwrap();
+            let result = eval(&expr, &mut typing::TypeEnv::new(), &mut ValEnv::new(), 0);
+            assert_eq!(output, result.unwrap().to_string());
+        }
+
+        // verboseではキャプチャした変数の値も表示する
+        let input = r"let x : lin bool = lin true;
+        let y : un int = un 2;
+        lin fn z : un bool { if z { x } else { free y; x } }";
+        let (_, expr) = parser::parse(input).unwrap();
+        let mut val_env = ValEnv::new();
+        let v = eval(&expr, &mut typing::TypeEnv::new(), &mut val_env, 0).unwrap();
+        assert_eq!("lin <closure fn z: un bool>", v.to_string());
+        assert_eq!(
+            "lin <closure fn z: un bool [x = lin true, y = un 2]>",
+            v.verbose(&val_env).to_string()
+        );
+    }
 }
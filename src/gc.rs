@@ -1,31 +1,217 @@
-use crate::eval::{ValEnvStack, *};
-use crate::parser::FnExpr;
-use crate::{eval, parser, typing};
+//! マーク&スイープ方式のガベージコレクタ
+//!
+//! クロージャがキャプチャした変数の値はヒープ上に確保され、
+//! クロージャはそのアドレスのみを保持する。
+//! 変数環境と評価途中の値をルートとしてマークし、到達できないオブジェクトを回収する。
+//! GCは生存しているオブジェクト数が閾値に達した場合のみ実行し、
+//! 実行後の閾値は回収後に生存しているオブジェクト数の2倍とする。
 
-// クロージャが持つ環境から, 不要な変数を削除する
+use crate::eval::ReturnVal;
+use std::fmt;
 
+/// ヒープ上のオブジェクト
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Object {
-    closure: Closure,
+    val: ReturnVal,
     is_marked: bool,
 }
+
+/// ヒープの統計情報
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Stats {
+    pub live_objects: usize, // 生存しているオブジェクト数
+    pub allocated: usize,    // これまでに確保したオブジェクト数
+    pub freed: usize,        // これまでに回収したオブジェクト数
+    pub collections: usize,  // GCの実行回数
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "live objects: {}, allocated: {}, freed: {}, collections: {}",
+            self.live_objects, self.allocated, self.freed, self.collections
+        )
+    }
+}
+
+/// 閾値の初期値
+const INITIAL_THRESHOLD: usize = 256;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct GC {
-    pub(crate) closures: Vec<Object>,
+    heap: Vec<Option<Object>>, // Noneは空きスロット
+    free: Vec<usize>,          // 空きスロットのアドレス
+    roots: Vec<usize>,         // 評価途中の値が参照するアドレス
+    stats: Stats,
+    threshold: usize,     // 生存しているオブジェクト数がこの値に達したらGCを実行
+    min_threshold: usize, // 閾値の下限
+}
+
+impl Default for GC {
+    fn default() -> Self {
+        GC::new()
+    }
 }
 
 impl GC {
     pub fn new() -> GC {
+        GC::with_threshold(INITIAL_THRESHOLD)
+    }
+
+    /// 閾値の初期値と下限をthresholdとしたGC
+    pub fn with_threshold(threshold: usize) -> GC {
         GC {
-            closures: Vec::new(),
+            heap: Vec::new(),
+            free: Vec::new(),
+            roots: Vec::new(),
+            stats: Stats::default(),
+            threshold,
+            min_threshold: threshold,
         }
     }
-    pub fn insert(&mut self, c: Closure) {
-        self.closures.push(Object {
-            closure: c,
+
+    /// 生存しているオブジェクト数が閾値に達し、GCを実行すべきか
+    pub fn needs_collect(&self) -> bool {
+        self.stats.live_objects >= self.threshold
+    }
+
+    /// 値をヒープに確保し、アドレスを返す
+    pub fn alloc(&mut self, val: ReturnVal) -> usize {
+        let obj = Object {
+            val,
             is_marked: false,
-        });
+        };
+        self.stats.allocated += 1;
+        self.stats.live_objects += 1;
+        if let Some(addr) = self.free.pop() {
+            self.heap[addr] = Some(obj);
+            addr
+        } else {
+            self.heap.push(Some(obj));
+            self.heap.len() - 1
+        }
+    }
+
+    /// アドレスが指す値を取得
+    pub fn get(&self, addr: usize) -> Option<&ReturnVal> {
+        match self.heap.get(addr) {
+            Some(Some(obj)) => Some(&obj.val),
+            _ => None,
+        }
+    }
+
+    /// 評価途中の値を一時的にルートへ追加し、pop_rootsに渡す位置を返す
+    pub fn push_root(&mut self, val: &ReturnVal) -> usize {
+        let mark = self.roots.len();
+        refs(val, &mut self.roots);
+        mark
+    }
+
+    /// push_rootで追加したルートを削除
+    pub fn pop_roots(&mut self, mark: usize) {
+        self.roots.truncate(mark);
     }
 
-    pub fn mark(&mut self, v: ValEnvStack, f: FnExpr) {
-        // closure内の変数を探索
+    /// rootsと一時的なルートから到達可能なオブジェクトをマークし、それ以外を回収する
+    pub fn collect(&mut self, mut roots: Vec<usize>) {
+        roots.extend_from_slice(&self.roots);
+        self.mark(roots);
+        self.sweep();
+        self.stats.collections += 1;
+        self.threshold = self
+            .stats
+            .live_objects
+            .saturating_mul(2)
+            .max(self.min_threshold);
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    // ルートから辿れるオブジェクトをマーク
+    fn mark(&mut self, mut stack: Vec<usize>) {
+        while let Some(addr) = stack.pop() {
+            if let Some(Some(obj)) = self.heap.get_mut(addr) {
+                if !obj.is_marked {
+                    obj.is_marked = true;
+                    // キャプチャした値がクロージャであれば、その先も辿る
+                    refs(&obj.val, &mut stack);
+                }
+            }
+        }
+    }
+
+    // マークされていないオブジェクトを回収し、マークを外す
+    fn sweep(&mut self) {
+        for (addr, slot) in self.heap.iter_mut().enumerate() {
+            match slot {
+                Some(obj) if obj.is_marked => obj.is_marked = false,
+                Some(_) => {
+                    *slot = None;
+                    self.free.push(addr);
+                    self.stats.freed += 1;
+                    self.stats.live_objects -= 1;
+                }
+                None => (),
+            }
+        }
+    }
+}
+
+/// 値が直接参照するヒープ上のアドレスをoutに追加
+pub(crate) fn refs(val: &ReturnVal, out: &mut Vec<usize>) {
+    match val {
+        ReturnVal::Bool(_, _) | ReturnVal::Int(_, _) | ReturnVal::Unit(_) => (),
+        ReturnVal::Pair(_, v1, v2) => {
+            refs(v1, out);
+            refs(v2, out);
+        }
+        ReturnVal::Inl(_, v) | ReturnVal::Inr(_, v) => refs(v, out),
+        ReturnVal::Fun(_, c) => out.extend(c.env.values()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{eval::ReturnVal, gc::*, parser::Qual};
+
+    #[test]
+    fn test_threshold() {
+        // 閾値に達するまではGCを実行しない
+        let mut gc = GC::with_threshold(4);
+        for _ in 0..3 {
+            gc.alloc(ReturnVal::Unit(Qual::Un));
+            assert!(!gc.needs_collect());
+        }
+        gc.alloc(ReturnVal::Unit(Qual::Un));
+        assert!(gc.needs_collect());
+
+        // 実行後の閾値は、生存しているオブジェクト数の2倍
+        let roots: Vec<usize> = (0..3).collect();
+        gc.collect(roots.clone());
+        assert_eq!(3, gc.stats().live_objects);
+        assert_eq!(6, gc.threshold);
+        for _ in 0..2 {
+            gc.alloc(ReturnVal::Unit(Qual::Un));
+        }
+        assert!(!gc.needs_collect());
+
+        // 生存しているオブジェクトが少なくなっても、閾値は下限を下回らない
+        gc.collect(Vec::new());
+        assert_eq!(0, gc.stats().live_objects);
+        assert_eq!(4, gc.threshold);
+
+        // 生存しているオブジェクトが増え続けても、GCの実行回数は確保した数の対数に比例
+        let mut gc = GC::with_threshold(4);
+        let mut roots = Vec::new();
+        for _ in 0..1000 {
+            roots.push(gc.alloc(ReturnVal::Unit(Qual::Un)));
+            if gc.needs_collect() {
+                gc.collect(roots.clone());
+            }
+        }
+        assert_eq!(8, gc.stats().collections);
     }
 }
@@ -1,22 +1,40 @@
-pub trait SafeAdd: Sized {
-    fn safe_add(&self, n: &Self) -> Option<Self>;
-}
+/// オーバーフローを検査する演算のトレイトと、
+/// 失敗時にfが返すエラーを返す関数を定義する
+macro_rules! safe_op {
+    ($trait:ident, $method:ident, $checked:ident) => {
+        pub trait $trait: Sized {
+            fn $method(&self, n: &Self) -> Option<Self>;
+        }
 
-impl SafeAdd for usize {
-    fn safe_add(&self, n: &Self) -> Option<Self> {
-        self.checked_add(*n)
-    }
-}
+        impl $trait for usize {
+            fn $method(&self, n: &Self) -> Option<Self> {
+                self.$checked(*n)
+            }
+        }
 
-pub fn safe_add<T, F, E>(dst: &mut T, src: &T, f: F) -> Result<(), E>
-where
-    T: SafeAdd,
-    F: Fn() -> E,
-{
-    if let Some(n) = dst.safe_add(src) {
-        *dst = n;
-        Ok(())
-    } else {
-        Err(f())
-    }
+        impl $trait for i64 {
+            fn $method(&self, n: &Self) -> Option<Self> {
+                self.$checked(*n)
+            }
+        }
+
+        pub fn $method<T, F, E>(dst: &mut T, src: &T, f: F) -> Result<(), E>
+        where
+            T: $trait,
+            F: Fn() -> E,
+        {
+            if let Some(n) = dst.$method(src) {
+                *dst = n;
+                Ok(())
+            } else {
+                Err(f())
+            }
+        }
+    };
 }
+
+safe_op!(SafeAdd, safe_add, checked_add);
+safe_op!(SafeSub, safe_sub, checked_sub);
+safe_op!(SafeMul, safe_mul, checked_mul);
+safe_op!(SafeDiv, safe_div, checked_div);
+safe_op!(SafeRem, safe_rem, checked_rem);
@@ -0,0 +1,228 @@
+//! 線形型システムを持つ言語Linzinのインタプリタ
+//!
+//! プログラムのパース、型の別名の解決、型付け、評価を行う。
+//!
+//! ```
+//! let expr = linzin::parse("let x : lin bool = lin true; lin <x, un false>").unwrap();
+//! assert_eq!("lin (lin bool * un bool)", linzin::typecheck(&expr).unwrap().to_string());
+//! assert_eq!("lin <lin true, un false>", linzin::evaluate(&expr).unwrap().to_string());
+//! ```
+
+pub mod alias;
+pub mod error;
+pub mod eval;
+pub mod gc;
+mod helper;
+pub mod parser;
+pub mod printer;
+pub mod typing;
+
+pub use error::{Error, ErrorKind};
+pub use eval::{ReturnVal, ValEnv};
+pub use parser::{Expr, TypeExpr};
+pub use typing::TypeEnv;
+
+use nom::error::convert_error;
+
+/// プログラム全体をパースし、型の別名を解決
+///
+/// 構文エラーは、ソースコードの該当箇所を示すメッセージを持つErrorKind::Syntaxとなる
+pub fn parse(src: &str) -> Result<Expr, Error> {
+    let mut expr = parse_syntax(src)?;
+    alias::resolve(&mut expr)?;
+    Ok(expr)
+}
+
+/// 型の別名を解決せずにパース
+fn parse_syntax(src: &str) -> Result<Expr, Error> {
+    match parser::parse(src) {
+        Ok((_, expr)) => Ok(expr),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+            Err(ErrorKind::Syntax(convert_error(src, e)).into())
+        }
+        Err(nom::Err::Incomplete(_)) => Err(ErrorKind::Syntax("incomplete input".into()).into()),
+    }
+}
+
+/// ソースコードをコメントと型の別名を保ったまま整形
+pub fn format(src: &str) -> Result<String, Error> {
+    let expr = parse_syntax(src)?;
+    let comments = parser::comments(src);
+    let mut out = printer::print_with_comments(&expr, src, &comments);
+    out.push('\n');
+    Ok(out)
+}
+
+/// 空の環境で式を型付け
+pub fn typecheck(expr: &Expr) -> Result<TypeExpr, Error> {
+    Interpreter::new().typecheck(&mut expr.clone())
+}
+
+/// 空の環境で式を型付けし、評価
+pub fn evaluate(expr: &Expr) -> Result<ReturnVal, Error> {
+    Interpreter::new().run(&mut expr.clone()).map(|(_, v)| v)
+}
+
+/// 型環境と変数環境を保持するインタプリタ
+///
+/// defで定義した変数は、以降に型付け・評価する式から参照できる
+#[derive(Debug, Clone)]
+pub struct Interpreter {
+    type_env: TypeEnv,
+    val_env: ValEnv,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        let mut type_env = TypeEnv::new();
+        let mut val_env = ValEnv::new();
+        // グローバルな環境を用意
+        type_env.push(0);
+        val_env.push(0);
+        Interpreter { type_env, val_env }
+    }
+
+    /// 式を型付け
+    ///
+    /// 評価で用いるため、型注釈を省略したletの型などを式に記録する。
+    /// 型付けに失敗した場合は、型環境を型付け前の状態に戻す
+    pub fn typecheck(&mut self, expr: &mut Expr) -> Result<TypeExpr, Error> {
+        let env = self.type_env.clone();
+        let ret = typing::typing(expr, &mut self.type_env, 0);
+        if ret.is_err() {
+            self.type_env = env;
+        }
+        ret
+    }
+
+    /// 型付け済みの式を評価
+    pub fn eval(&mut self, expr: &Expr) -> Result<ReturnVal, Error> {
+        eval::eval(expr, &mut self.type_env, &mut self.val_env, 0)
+    }
+
+    /// 式を型付けし、評価
+    pub fn run(&mut self, expr: &mut Expr) -> Result<(TypeExpr, ReturnVal), Error> {
+        let ty = self.typecheck(expr)?;
+        let val = self.eval(expr)?;
+        Ok((ty, val))
+    }
+
+    pub fn type_env(&self) -> &TypeEnv {
+        &self.type_env
+    }
+
+    pub fn val_env(&self) -> &ValEnv {
+        &self.val_env
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::{fs, path::Path};
+
+    #[test]
+    fn test_interpreter() {
+        // defで定義した変数は以降の式から参照できる
+        let mut interp = Interpreter::new();
+        let mut expr = parse("def x : un int = un 3;").unwrap();
+        interp.run(&mut expr).unwrap();
+        assert_eq!("un int", interp.type_env().get("x").unwrap().to_string());
+        assert_eq!(
+            Some(&ReturnVal::Int(parser::Qual::Un, 3)),
+            interp.val_env().get("x")
+        );
+
+        let mut expr = parse("(x + un 1)").unwrap();
+        let (ty, val) = interp.run(&mut expr).unwrap();
+        assert_eq!("un int", ty.to_string());
+        assert_eq!(ReturnVal::Int(parser::Qual::Un, 4), val);
+
+        // 型付けで消費されたlinの変数も評価では参照でき、評価後に取り除かれる
+        let mut expr = parse("def y : lin bool = lin true;").unwrap();
+        interp.run(&mut expr).unwrap();
+        let mut expr = parse("let z = y; z").unwrap();
+        let (ty, val) = interp.run(&mut expr).unwrap();
+        assert_eq!("lin bool", ty.to_string());
+        assert_eq!(ReturnVal::Bool(parser::Qual::Lin, true), val);
+        assert_eq!(None, interp.val_env().get("y"));
+
+        // 型付けに失敗しても、変数は消費されない
+        let mut expr = parse("def w : lin bool = lin false;").unwrap();
+        interp.run(&mut expr).unwrap();
+        for input in ["un fn v : un bool { u }", "lin <w, (un 1 + un true)>"] {
+            let mut expr = parse(input).unwrap();
+            assert!(interp.typecheck(&mut expr).is_err(), "{input}");
+        }
+        let mut expr = parse("w").unwrap();
+        assert_eq!(
+            ReturnVal::Bool(parser::Qual::Lin, false),
+            interp.run(&mut expr).unwrap().1
+        );
+
+        // 構文エラー
+        let err = parse("let x").unwrap_err();
+        assert!(matches!(*err.kind, ErrorKind::Syntax(_)));
+        assert_eq!("parse error", err.parse_label());
+
+        // 型の別名は型付けの前に解決する
+        let mut expr = parse("type B = lin bool; let x : B = lin true; x").unwrap();
+        assert_eq!("lin bool", interp.typecheck(&mut expr).unwrap().to_string());
+        let err = parse("let x : B = lin true; x").unwrap_err();
+        assert_eq!(ErrorKind::UnknownType("B".to_string()), *err.kind);
+        assert_eq!("resolution error", err.parse_label());
+        // 整形では別名を保つ
+        assert_eq!(
+            "type B = lin bool;\nlet x : B = lin true;\nx\n",
+            format("type B = lin bool; let x : B = lin true; x").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_codes() {
+        // codes/のプログラムのうち、ex*は型付けでき、err*は型付けに失敗し、parse_err*はパースに失敗する。
+        // repl*はREPLへの入力で、各行を順に型付けでき、defで定義した変数は以降の行から参照できる
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("codes");
+        let mut count = 0;
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|e| e != "lin") {
+                continue;
+            }
+            let name = path.file_name().unwrap().to_str().unwrap().to_string();
+            let src = fs::read_to_string(&path).unwrap();
+            if name.starts_with("parse_err") {
+                // 型の別名や修飾子変数の解決を含め、パースに失敗する
+                assert!(parse(&src).is_err(), "{name}");
+                count += 1;
+                continue;
+            }
+            if name.starts_with("repl") {
+                let mut interp = Interpreter::new();
+                for line in src.lines().filter(|l| !l.trim().is_empty()) {
+                    let ret = parse(line).and_then(|mut expr| interp.typecheck(&mut expr));
+                    assert!(ret.is_ok(), "{name}: {}", ret.unwrap_err());
+                }
+                count += 1;
+                continue;
+            }
+            let ret = parse(&src).and_then(|expr| typecheck(&expr));
+            if name.starts_with("ex") {
+                assert!(ret.is_ok(), "{name}: {}", ret.unwrap_err());
+            } else if name.starts_with("err") {
+                let err = ret.expect_err(&name);
+                assert!(!matches!(*err.kind, ErrorKind::Syntax(_)), "{name}: {err}");
+            } else {
+                panic!("{name}: unknown kind of program");
+            }
+            count += 1;
+        }
+        assert!(count > 0);
+    }
+}
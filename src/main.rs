@@ -1,103 +1,222 @@
-mod eval;
-mod gc;
-mod helper;
-mod parser;
-mod typing;
-use nom::error::convert_error;
-use nom::{error::VerboseError, IResult};
+use linzin::{Expr, Interpreter};
 use rustyline::Editor;
-use std::{env, error::Error, fs};
+use std::{env, fs, process::ExitCode};
 
-fn main() -> Result<(), Box<dyn Error>> {
+const USAGE: &str = "usage: linzin <command>
+
+commands:
+    check FILE             typecheck FILE and print its type
+    run [--verbose] FILE   typecheck and evaluate FILE, and print its value
+                           (--verbose also prints the values captured by closures)
+    ast [--json] FILE      print the AST of FILE
+    fmt [--check] FILE     format FILE in place, or check that it is formatted
+    repl                   start the REPL (default)";
+
+fn main() -> ExitCode {
     // コマンドライン引数の検査
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        // eprintln!("以下のようにファイル名を指定して実行してください\ncargo run codes/ex1.lin");
-        // return Err("引数が不足".into());
-        let mut rl = Editor::<()>::new().unwrap();
-        let mut ctx = typing::TypeEnv::new();
-        let mut val_env = eval::ValEnv::new();
-        // prepare global environment
-        ctx.push(0);
-        val_env.push(0);
-        println!(
-            "Welcome to Linzin!\nLet's type <expression>\nTo show the environment, please type env"
-        );
-        loop {
-            // 1行読み込んでパースし成功すれば評価
-            if let Ok(readline) = rl.readline(">> ") {
-                let content = skip_comment(&readline); // コメントを削除
-                if content.eq("env") {
-                    println!("[Type Environment]:\n {:?}", ctx);
-                    println!("[Variable Environment]\n {:?}", val_env);
-                    continue;
-                }
-                let ast = parser::parse(&content); // パース
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let ret = match args.as_slice() {
+        [] | ["repl"] => repl(),
+        ["check", file] => check(file),
+        ["run", file] => run(file, false),
+        ["run", "--verbose", file] => run(file, true),
+        ["ast", file] => ast(file, false),
+        ["ast", "--json", file] => ast(file, true),
+        ["fmt", file] => fmt(file, false),
+        ["fmt", "--check", file] => fmt(file, true),
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    match ret {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(code) => code,
+    }
+}
+
+/// ファイルを読み込んでパースする。失敗した場合はエラーを表示する
+fn load(file: &str) -> Result<(String, Expr), ExitCode> {
+    let content = match fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("{file}: {e}");
+            return Err(ExitCode::FAILURE);
+        }
+    };
+    match linzin::parse(&content) {
+        Ok(expr) => Ok((content, expr)),
+        Err(e) => {
+            eprint!("{}:\n{}", e.parse_label(), e.render(&content));
+            Err(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// 型付けのみを行う
+fn check(file: &str) -> Result<(), ExitCode> {
+    let (content, expr) = load(file)?;
+    match linzin::typecheck(&expr) {
+        Ok(ty) => {
+            println!("{ty}");
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("typing error:\n{}", e.render(&content));
+            Err(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// 型付けと評価を行い、値のみを表示。verboseの場合はクロージャがキャプチャした値も表示する
+fn run(file: &str, verbose: bool) -> Result<(), ExitCode> {
+    let (content, mut expr) = load(file)?;
+    let mut interp = Interpreter::new();
+    if let Err(e) = interp.typecheck(&mut expr) {
+        eprintln!("typing error:\n{}", e.render(&content));
+        return Err(ExitCode::FAILURE);
+    }
+    match interp.eval(&expr) {
+        Ok(v) if verbose => {
+            println!("{}", v.verbose(interp.val_env()));
+            Ok(())
+        }
+        Ok(v) => {
+            println!("{v}");
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("evaluation error:\n{}", e.render(&content));
+            Err(ExitCode::FAILURE)
+        }
+    }
+}
 
-                interpret(&content, &mut ctx, &mut val_env, ast);
-            } else {
-                break;
+/// 抽象構文木を表示
+fn ast(file: &str, json: bool) -> Result<(), ExitCode> {
+    let (_, expr) = load(file)?;
+    if json {
+        match serde_json::to_string_pretty(&expr) {
+            Ok(s) => println!("{s}"),
+            Err(e) => {
+                eprintln!("{e}");
+                return Err(ExitCode::FAILURE);
             }
         }
+    } else {
+        println!("{:#?}", expr);
+    }
+    Ok(())
+}
+
+/// コメントを保ったまま整形し、ファイルを書き換える
+///
+/// checkの場合は書き換えず、整形済みでなければ失敗とする
+fn fmt(file: &str, check: bool) -> Result<(), ExitCode> {
+    let content = match fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("{file}: {e}");
+            return Err(ExitCode::FAILURE);
+        }
+    };
+    let formatted = match linzin::format(&content) {
+        Ok(formatted) => formatted,
+        Err(e) => {
+            eprintln!("{}:\n{e}", e.parse_label());
+            return Err(ExitCode::FAILURE);
+        }
+    };
+    if formatted == content {
         return Ok(());
     }
+    if check {
+        eprintln!("{file}: not formatted");
+        return Err(ExitCode::FAILURE);
+    }
+    if let Err(e) = fs::write(file, formatted) {
+        eprintln!("{file}: {e}");
+        return Err(ExitCode::FAILURE);
+    }
+    Ok(())
+}
 
-    // ファイル読み込み
-    let content = fs::read_to_string(&args[1])?;
-    let content = skip_comment(&content); // コメントを削除
-    let ast = parser::parse(&content); // パース
-    println!("AST:\n{:#?}\n", ast);
-    let mut ctx = typing::TypeEnv::new();
-    let mut val_env = eval::ValEnv::new();
-    interpret(&content, &mut ctx, &mut val_env, ast);
+/// 1行読み込んでパースし、成功すれば評価する
+///
+/// verboseと入力するたびに、クロージャがキャプチャした値を表示するかを切り替える
+fn repl() -> Result<(), ExitCode> {
+    let mut rl = match Editor::<()>::new() {
+        Ok(rl) => rl,
+        Err(e) => {
+            eprintln!("{e}");
+            return Err(ExitCode::FAILURE);
+        }
+    };
+    let mut interp = Interpreter::new();
+    let mut verbose = false;
+    println!(
+        "Welcome to Linzin!\nLet's type <expression>\nTo show the environment, please type env\n\
+        To show the values captured by closures, please type verbose"
+    );
+    #[allow(clippy::while_let_loop)]
+    loop {
+        // 1行読み込んでパースし成功すれば評価
+        if let Ok(readline) = rl.readline(">> ") {
+            if readline.trim() == "env" {
+                println!("[Type Environment]:\n {:?}", interp.type_env());
+                println!("[Variable Environment]\n {:?}", interp.val_env());
+                println!("[Heap]\n {}", interp.val_env().heap_stats());
+                continue;
+            }
+            if readline.trim() == "verbose" {
+                verbose = !verbose;
+                println!("verbose mode: {}", if verbose { "on" } else { "off" });
+                continue;
+            }
+            interpret(&readline, &mut interp, verbose);
+        } else {
+            break;
+        }
+    }
     Ok(())
 }
 
-fn interpret(
-    content: &str,
-    ctx: &mut typing::TypeEnv,
-    val_env: &mut eval::ValEnv,
-    ast: IResult<&str, parser::Expr, VerboseError<&str>>,
-) {
-    match ast {
-        Ok((_, expr)) => {
-            // println!("[Expression]\n{content}");
-            // typing
-            let ty = match typing::typing(&expr, ctx, 0) {
-                Ok(a) => a,
-                Err(e) => {
-                    println!("typing error:\n{e}");
-                    return;
-                }
-            };
-            println!("[Type]\n{}", ty);
+fn interpret(content: &str, interp: &mut Interpreter, verbose: bool) {
+    // パース
+    let mut expr = match linzin::parse(content) {
+        Ok(expr) => expr,
+        Err(e) => {
+            eprint!("{}:\n{}", e.parse_label(), e.render(content));
+            return;
+        }
+    };
 
-            // evaluation
-            let result = match eval::eval(&expr, ctx, val_env, 0) {
-                Ok(v) => v,
-                Err(e) => {
-                    println!("evaluation error:\n{e}");
-                    return;
-                }
-            };
-            println!("[Evaluation]\n{}", result);
-        }
-        Err(nom::Err::Error(e)) => {
-            let msg = convert_error(content, e);
-            eprintln!("parse error:\n{msg}");
-        }
-        _ => (),
+    // typing
+    let ty = match interp.typecheck(&mut expr) {
+        Ok(a) => a,
+        Err(e) => {
+            println!("typing error:\n{}", e.render(content));
+            return;
+        }
+    };
+    // defの場合は、束縛した変数と推論した型を表示
+    match &expr {
+        Expr::Def(e) => println!("[Type]\n{} : {}", e.var, ty),
+        _ => println!("[Type]\n{}", ty),
     }
-}
 
-// strip comment from input
-fn skip_comment(input: &str) -> String {
-    let mut new = String::new();
-    for i in input.lines() {
-        match i.find("//") {
-            Some(start) => new.push_str(&i[..start]),
-            None => new.push_str(i),
+    // evaluation
+    let result = match interp.eval(&expr) {
+        Ok(v) => v,
+        Err(e) => {
+            println!("evaluation error:\n{}", e.render(content));
+            return;
         }
+    };
+    if verbose {
+        println!("[Evaluation]\n{}", result.verbose(interp.val_env()));
+    } else {
+        println!("[Evaluation]\n{}", result);
     }
-    new
 }
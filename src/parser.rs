@@ -6,73 +6,372 @@
 //! ## 構文
 //!
 //! ```text
-//! <VAR>   := 1文字以上のアルファベットから成り立つ変数
+//! <VAR>   := [a-zA-Z_][a-zA-Z0-9_']* (予約語を除く)
 //!
-//! <E>     := <LET> | <IF> | <SPLIT> | <FREE> | <APP> | <VAR> | <QVAL> | <DEF> | <ENV>
+//! コメント: `//`から行末までと、`/*`から`*/`まで。空白と同様に読み飛ばす
 //!
-//! <LET>   := let <VAR> : <T> = <E>; <E>
+//! <E>     := <LET> | <IF> | <SPLIT> | <CASE> | <FREE> | <APP> | <OP> | <VAR> | <QVAL> | <DEF> | <ENV> | <TYPE> | <SEQ>
+//!
+//! <LET>   := let [rec] <VAR> [: <T>] = <E>; <E>
 //! <IF>    := if <E> { <E> } else { <E> }
 //! <SPLIT> := split <E> as <VAR>, <VAR> { <E> }
-//! <FREE>  := free <E>; <E>
-//! <APP>   := ( <E> <E> )
-//! <DEF>   := def <VAR> : <T> = <E>; (REPL専用)
+//! <CASE>  := case <E> of inl <VAR> { <E> } | inr <VAR> { <E> }
+//! <FREE>  := free <VAR>; <E> | free <VAR>
+//! <SEQ>   := <E>; <E>
+//! <APP>   := ( <E> <E> { <E> } )
+//! <OP>    := ( <E> <BINOP> <E> )
+//! <DEF>   := def <VAR> [: <T>] = <E>; (REPL専用)
 //! <ENV>   := env; <E>
+//! <TYPE>  := type <VAR> = <T>; <E>
 //!
 //! <Q>     := lin | un | aff
+//! <QT>    := lin | un | aff | <VAR> (型の修飾子。<VAR>はforallで量化した修飾子変数)
+//!
+//! <BINOP> := + | - | * | / | % | < | <= | == | !=
 //!
 //! 値
 //! <QVAL>  := <Q> <VAL>
-//! <VAL>   := <B> | <PAIR> | <FN>
+//! <VAL>   := <B> | <N> | <UNIT> | <PAIR> | <INJ> | <FN>
 //! <B>     := true | false
+//! <N>     := 整数リテラル
+//! <UNIT>  := ()
 //! <PAIR>  := < <E> , <E> >
-//! <FN>    := fn <VAR> : <T> { <E> }
+//! <INJ>   := inl ( <T> + <T> ) <E> | inr ( <T> + <T> ) <E>
+//! <FN>    := fn <VAR> : <T> { , <VAR> : <T> } { <E> }
 //!
 //! 型
-//! <T>     := <Q> <P>
+//! <T>     := forall <PARAM> { , <PARAM> } . <T> | <QT> <P> | <VAR> (型の別名か、forallで量化した型変数)
+//! <PARAM> := <VAR> | <VAR> : <Q> (修飾子変数か、修飾子の上限を持つ型変数)
 //! <P>     := bool |
+//!            int |
+//!            unit |
 //!            ( <T> * <T> )
+//!            ( <T> + <T> )
 //!            ( <T> -> <T> )
 //! ```
 
+use crate::printer;
 use nom::{
     branch::alt,
-    bytes::complete::tag,
-    character::complete::{alpha1, char, multispace0, multispace1},
-    error::VerboseError,
-    sequence::delimited,
+    bytes::complete::{tag, take_until},
+    character::complete::{alpha1, alphanumeric1, char, digit1, multispace1, not_line_ending},
+    combinator::{opt, recognize, value, verify},
+    error::{ErrorKind, ParseError, VerboseError, VerboseErrorKind},
+    multi::{many0, many1, separated_list1},
+    sequence::{delimited, pair, preceded},
     IResult,
 };
-use std::fmt::{self};
+use serde::Serialize;
+use std::{
+    collections::BTreeSet,
+    fmt::{self},
+};
 
 /// 抽象構文木
 ///
 /// ```text
-/// <E> := <LET> | <IF> | <SPLIT> | <FREE> | <APP> | <VAR> | <QVAL>
+/// <E> := <LET> | <IF> | <SPLIT> | <CASE> | <FREE> | <APP> | <OP> | <VAR> | <QVAL> | <DEF> | <ENV> | <TYPE> | <SEQ>
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub enum Expr {
-    Let(LetExpr),     // let式
-    If(IfExpr),       // if式
-    Split(SplitExpr), // split式
-    Free(FreeExpr),   // free文
-    App(AppExpr),     // 関数適用
-    Var(String),      // 変数
-    QVal(QValExpr),   // 値
-    Def(DefExpr),     // 変数定義
-    Env(EnvExpr),     // 環境表示
+    Let(LetExpr),      // let式
+    If(IfExpr),        // if式
+    Split(SplitExpr),  // split式
+    Case(CaseExpr),    // case式
+    Free(FreeExpr),    // free文
+    App(AppExpr),      // 関数適用
+    BinOp(BinOpExpr),  // 二項演算
+    Var(VarExpr),      // 変数
+    QVal(QValExpr),    // 値
+    Def(DefExpr),      // 変数定義
+    Env(EnvExpr),      // 環境表示
+    Type(TypeDefExpr), // 型の別名の定義
+    Seq(SeqExpr),      // 逐次実行
+}
+
+/// ソースコードとして表示
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", printer::print(self))
+    }
+}
+
+/// 変数
+///
+/// ```text
+/// <VAR> := [a-zA-Z_][a-zA-Z0-9_']* (予約語を除く)
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VarExpr {
+    pub var: String,
+    pub span: Span,
+}
+
+/// コメント
+///
+/// パース時には空白と同様に読み飛ばし、整形時にソースコード上の位置をもとに出力し直す
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Comment {
+    pub text: String, // `//`や`/* */`を含むコメントの文字列
+    pub span: Span,
+}
+
+/// ソースコード上の位置
+///
+/// 式の先頭と末尾のバイトオフセット（`start..end`）を保持する
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// 開始位置の行番号と列番号を返す（ともに1始まり）
+    pub fn line_col(&self, src: &str) -> (usize, usize) {
+        let start = self.start.min(src.len());
+        let before = &src[..start];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |n| n + 1);
+        let col = src[line_start..start].chars().count() + 1;
+        (line, col)
+    }
+}
+
+impl Expr {
+    /// 式のソースコード上の位置
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Let(e) => e.span,
+            Expr::If(e) => e.span,
+            Expr::Split(e) => e.span,
+            Expr::Case(e) => e.span,
+            Expr::Free(e) => e.span,
+            Expr::App(e) => e.span,
+            Expr::BinOp(e) => e.span,
+            Expr::Var(e) => e.span,
+            Expr::QVal(e) => e.span,
+            Expr::Def(e) => e.span,
+            Expr::Env(e) => e.span,
+            Expr::Type(e) => e.span,
+            Expr::Seq(e) => e.span,
+        }
+    }
+
+    /// 式中の自由変数の集合
+    pub fn free_vars(&self) -> BTreeSet<String> {
+        let mut vars = BTreeSet::new();
+        self.collect_free_vars(&mut vars);
+        vars
+    }
+
+    fn collect_free_vars(&self, vars: &mut BTreeSet<String>) {
+        match self {
+            Expr::Let(e) => {
+                if e.rec {
+                    vars.extend(free_vars_except(&e.expr1, &[&e.var]));
+                } else {
+                    e.expr1.collect_free_vars(vars);
+                }
+                vars.extend(free_vars_except(&e.expr2, &[&e.var]));
+            }
+            Expr::If(e) => {
+                e.cond_expr.collect_free_vars(vars);
+                e.then_expr.collect_free_vars(vars);
+                e.else_expr.collect_free_vars(vars);
+            }
+            Expr::Split(e) => {
+                e.expr.collect_free_vars(vars);
+                vars.extend(free_vars_except(&e.body, &[&e.left, &e.right]));
+            }
+            Expr::Case(e) => {
+                e.expr.collect_free_vars(vars);
+                vars.extend(free_vars_except(&e.inl_expr, &[&e.inl_var]));
+                vars.extend(free_vars_except(&e.inr_expr, &[&e.inr_var]));
+            }
+            Expr::Free(e) => {
+                vars.insert(e.var.clone());
+                e.expr.collect_free_vars(vars);
+            }
+            Expr::App(e) => {
+                e.expr1.collect_free_vars(vars);
+                e.expr2.collect_free_vars(vars);
+            }
+            Expr::BinOp(e) => {
+                e.expr1.collect_free_vars(vars);
+                e.expr2.collect_free_vars(vars);
+            }
+            Expr::Var(e) => {
+                vars.insert(e.var.clone());
+            }
+            Expr::QVal(e) => match &e.val {
+                ValExpr::Bool(_) | ValExpr::Int(_) | ValExpr::Unit => (),
+                ValExpr::Pair(e1, e2) => {
+                    e1.collect_free_vars(vars);
+                    e2.collect_free_vars(vars);
+                }
+                ValExpr::Inl(e) | ValExpr::Inr(e) => e.expr.collect_free_vars(vars),
+                ValExpr::Fun(e) => vars.extend(e.free_vars()),
+            },
+            Expr::Def(e) => e.expr.collect_free_vars(vars),
+            Expr::Env(e) => e.expr.collect_free_vars(vars),
+            Expr::Type(e) => e.expr.collect_free_vars(vars),
+            Expr::Seq(e) => {
+                e.expr1.collect_free_vars(vars);
+                e.expr2.collect_free_vars(vars);
+            }
+        }
+    }
+
+    /// 式に含まれる全ての位置情報をfで変換
+    pub(crate) fn map_span<F: Fn(Span) -> Span>(&mut self, f: &F) {
+        match self {
+            Expr::Let(e) => {
+                e.span = f(e.span);
+                e.expr1.map_span(f);
+                e.expr2.map_span(f);
+            }
+            Expr::If(e) => {
+                e.span = f(e.span);
+                e.cond_expr.map_span(f);
+                e.then_expr.map_span(f);
+                e.else_expr.map_span(f);
+            }
+            Expr::Split(e) => {
+                e.span = f(e.span);
+                e.expr.map_span(f);
+                e.body.map_span(f);
+            }
+            Expr::Case(e) => {
+                e.span = f(e.span);
+                e.expr.map_span(f);
+                e.inl_expr.map_span(f);
+                e.inr_expr.map_span(f);
+            }
+            Expr::Free(e) => {
+                e.span = f(e.span);
+                e.expr.map_span(f);
+            }
+            Expr::App(e) => {
+                e.span = f(e.span);
+                e.expr1.map_span(f);
+                e.expr2.map_span(f);
+            }
+            Expr::BinOp(e) => {
+                e.span = f(e.span);
+                e.expr1.map_span(f);
+                e.expr2.map_span(f);
+            }
+            Expr::Var(e) => e.span = f(e.span),
+            Expr::QVal(e) => {
+                e.span = f(e.span);
+                match &mut e.val {
+                    ValExpr::Bool(_) | ValExpr::Int(_) | ValExpr::Unit => (),
+                    ValExpr::Pair(e1, e2) => {
+                        e1.map_span(f);
+                        e2.map_span(f);
+                    }
+                    ValExpr::Inl(e) | ValExpr::Inr(e) => e.expr.map_span(f),
+                    ValExpr::Fun(e) => {
+                        for p in e.rest.iter_mut() {
+                            p.span = f(p.span);
+                        }
+                        e.expr.map_span(f);
+                    }
+                }
+            }
+            Expr::Def(e) => {
+                e.span = f(e.span);
+                e.expr.map_span(f);
+            }
+            Expr::Env(e) => {
+                e.span = f(e.span);
+                e.expr.map_span(f);
+            }
+            Expr::Type(e) => {
+                e.span = f(e.span);
+                e.expr.map_span(f);
+            }
+            Expr::Seq(e) => {
+                e.span = f(e.span);
+                e.expr1.map_span(f);
+                e.expr2.map_span(f);
+            }
+        }
+    }
 }
 
 /// 関数適用
 ///
+/// 複数の引数への適用は、パース時に関数適用の入れ子に変換する
+///
 /// ```text
-/// <APP> := ( <E> <E> )
+/// <APP> := ( <E> <E> { <E> } )
 ///
 /// (expr1 expr2)
+/// (f a b) => ((f a) b)
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct AppExpr {
     pub expr1: Box<Expr>,
     pub expr2: Box<Expr>,
+    pub span: Span,
+}
+
+/// 二項演算
+///
+/// ```text
+/// <OP> := ( <E> <BINOP> <E> )
+///
+/// (expr1 op expr2)
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BinOpExpr {
+    pub op: BinOp,
+    pub expr1: Box<Expr>,
+    pub expr2: Box<Expr>,
+    pub span: Span,
+}
+
+/// 二項演算子
+///
+/// ```text
+/// <BINOP> := + | - | * | / | % | < | <= | == | !=
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum BinOp {
+    Add, // +
+    Sub, // -
+    Mul, // *
+    Div, // /
+    Mod, // %
+    Lt,  // <
+    Le,  // <=
+    Eq,  // ==
+    Ne,  // !=
+}
+
+impl BinOp {
+    /// 比較演算子か
+    pub fn is_comparison(&self) -> bool {
+        matches!(self, BinOp::Lt | BinOp::Le | BinOp::Eq | BinOp::Ne)
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Mod => "%",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+        };
+        write!(f, "{op}")
+    }
 }
 
 /// if式
@@ -86,15 +385,18 @@ pub struct AppExpr {
 ///     else_expr
 /// }
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct IfExpr {
     pub cond_expr: Box<Expr>,
     pub then_expr: Box<Expr>,
     pub else_expr: Box<Expr>,
+    pub span: Span,
 }
 
 /// split式
 ///
+/// 型付けの際に、評価で用いるためleftとrightの型をtysに記録する
+///
 /// ```text
 /// <SPLIT> := split <E> as <VAR>, <VAR> { <E> }
 ///
@@ -102,106 +404,253 @@ pub struct IfExpr {
 ///     body
 /// }
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct SplitExpr {
     pub expr: Box<Expr>,
     pub left: String,
     pub right: String,
     pub body: Box<Expr>,
+    #[serde(skip)]
+    pub tys: Option<(TypeExpr, TypeExpr)>, // 型付けで求めたleftとrightの型
+    pub span: Span,
+}
+
+/// case式
+///
+/// 型付けの際に、評価で用いるためinl_varとinr_varの型をtysに記録する
+///
+/// ```text
+/// <CASE> := case <E> of inl <VAR> { <E> } | inr <VAR> { <E> }
+///
+/// case expr of inl inl_var {
+///     inl_expr
+/// } | inr inr_var {
+///     inr_expr
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CaseExpr {
+    pub expr: Box<Expr>,
+    pub inl_var: String,
+    pub inl_expr: Box<Expr>,
+    pub inr_var: String,
+    pub inr_expr: Box<Expr>,
+    #[serde(skip)]
+    pub tys: Option<(TypeExpr, TypeExpr)>, // 型付けで求めたinl_varとinr_varの型
+    pub span: Span,
 }
 
 /// let式
 ///
+/// recの場合、expr1は関数で、関数本体からvarを参照して再帰呼び出しできる
+///
+/// 型注釈を省略した場合は、expr1の型をvarの型とする。
+/// recの場合は、本体の型付けの前に型が必要なため省略できない。
+/// 省略した場合の型は型付けの際にinferredに記録し、評価で用いる
+///
 /// ```text
-/// <LET>   := let <VAR> : <T> = <E> { <E> }
+/// <LET>   := let [rec] <VAR> [: <T>] = <E>; <E>
 ///
-/// let var : ty = expr1 { expr2 }
+/// let var : ty = expr1; expr2
+/// let var = expr1; expr2
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct LetExpr {
+    pub rec: bool,
     pub var: String,
-    pub ty: TypeExpr,
+    pub ty: Option<TypeExpr>, // 型注釈
+    #[serde(skip)]
+    pub inferred: Option<TypeExpr>, // 型注釈を省略した場合に、型付けで求めた型
     pub expr1: Box<Expr>,
     pub expr2: Box<Expr>,
+    pub span: Span,
 }
 
 /// def
 ///
+/// 型注釈を省略した場合は、exprの型をvarの型とする
+///
 /// ```text
-/// <Def>   := def <VAR> : <T> = <E>;
+/// <Def>   := def <VAR> [: <T>] = <E>;
 ///
 /// def var : ty = expr1;
+/// def var = expr1;
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct DefExpr {
     pub var: String,
-    pub ty: TypeExpr,
+    pub ty: Option<TypeExpr>, // 型注釈
     pub expr: Box<Expr>,
+    pub span: Span,
 }
 
-/// 値。真偽値、関数、ペア値などになる
+/// 値。真偽値、整数、unit、関数、ペア値、直和値などになる
 ///
 /// ```text
-/// <VAL>  := <B> | <PAIR> | <FN>
+/// <VAL>  := <B> | <N> | <UNIT> | <PAIR> | <INJ> | <FN>
 /// <B>    := true | false
+/// <N>    := 整数リテラル
+/// <UNIT> := ()
 /// <PAIR> := < <E> , <E> >
+/// <INJ>  := inl ( <T> + <T> ) <E> | inr ( <T> + <T> ) <E>
 /// <FN>   := fn <VAR> : <T> { <E> }
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub enum ValExpr {
     Bool(bool),                 // 真偽値リテラル
+    Int(i64),                   // 整数リテラル
+    Unit,                       // unit値
     Pair(Box<Expr>, Box<Expr>), // ペア
+    Inl(InjExpr),               // 直和の左側
+    Inr(InjExpr),               // 直和の右側
     Fun(FnExpr),                // 関数（λ抽象）
 }
 
+/// 直和型への注入
+///
+/// ```text
+/// <INJ> := inl ( <T> + <T> ) <E> | inr ( <T> + <T> ) <E>
+///
+/// inl (left_ty + right_ty) expr
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InjExpr {
+    pub left_ty: TypeExpr,
+    pub right_ty: TypeExpr,
+    pub expr: Box<Expr>,
+}
+
 /// 修飾子
 ///
+/// 修飾子変数は型の中にのみ現れ、forallで量化する。
+/// 修飾子変数はun以上lin以下の任意の修飾子を表すため、lin型と同様に一度だけ使える
+///
 /// ```text
-/// <Q> := lin | un
+/// <Q>  := lin | un | aff
+/// <QT> := lin | un | aff | <VAR>
 /// ```
-#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
 pub enum Qual {
-    Lin, // 線形型
-    Un,  // 制約のない一般的な型
-    Aff, // affine型
+    Lin,         // 線形型
+    Un,          // 制約のない一般的な型
+    Aff,         // affine型
+    Var(String), // 修飾子変数
+}
+
+impl Qual {
+    /// 二つの修飾子のうち制約の強い方を返す（un < aff < lin）
+    ///
+    /// 修飾子変数と順序の定まらない修飾子の場合はlinとする
+    pub fn join(&self, other: &Qual) -> Qual {
+        match (self, other) {
+            (q1, q2) if q1 == q2 => q1.clone(),
+            (Qual::Un, q) | (q, Qual::Un) => q.clone(),
+            _ => Qual::Lin,
+        }
+    }
+
+    /// 二つの修飾子のうち制約の弱い方を返す
+    ///
+    /// 修飾子変数と順序の定まらない修飾子の場合はunとする
+    pub fn meet(&self, other: &Qual) -> Qual {
+        match (self, other) {
+            (q1, q2) if q1 == q2 => q1.clone(),
+            (Qual::Lin, q) | (q, Qual::Lin) => q.clone(),
+            _ => Qual::Un,
+        }
+    }
+
+    /// selfがother以下の制約かどうか（un ≤ aff ≤ lin, un ≤ q ≤ lin）
+    ///
+    /// 制約の弱い修飾子の値は、制約の強い修飾子の値として扱える
+    pub fn is_sub(&self, other: &Qual) -> bool {
+        self.join(other) == *other
+    }
+}
+
+impl fmt::Display for Qual {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Qual::Lin => write!(f, "lin"),
+            Qual::Un => write!(f, "un"),
+            Qual::Aff => write!(f, "aff"),
+            Qual::Var(q) => write!(f, "{q}"),
+        }
+    }
 }
 
 /// 修飾子付き値
 ///
-/// ```
+/// ```text
 /// <QV> := <Q> <VAL>
 /// ```
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
 pub struct QValExpr {
     pub qual: Qual,
     pub val: ValExpr,
+    pub span: Span,
 }
 
 /// 関数
 ///
+/// 複数の引数を持つ関数の二つめ以降の引数はrestに保持し、
+/// alias::resolveで引数の型を解決した後に関数の入れ子に変換する
+///
 /// ```text
-/// <FN> := fn <VAR> : <T> { <E> }
+/// <FN> := fn <VAR> : <T> { , <VAR> : <T> } { <E> }
 ///
 /// fn var : ty { expr }
+/// fn var : ty, rest[0].var : rest[0].ty, ... { expr }
 /// ```
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
 pub struct FnExpr {
     pub var: String,
     pub ty: TypeExpr,
     pub expr: Box<Expr>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rest: Vec<FnParam>, // 二つめ以降の引数
+}
+
+/// 複数の引数を持つ関数の、二つめ以降の引数
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
+pub struct FnParam {
+    pub var: String,
+    pub ty: TypeExpr,
+    pub span: Span, // 引数から関数の終わりまで。入れ子にした関数の位置となる
+}
+
+impl FnExpr {
+    /// 関数本体の自由変数のうち、引数以外のもの。クロージャがキャプチャする変数となる
+    pub fn free_vars(&self) -> BTreeSet<String> {
+        let mut vars = vec![&self.var];
+        vars.extend(self.rest.iter().map(|p| &p.var));
+        free_vars_except(&self.expr, &vars)
+    }
+}
+
+/// exprの自由変数からvarsで束縛される変数を除いたもの
+fn free_vars_except(expr: &Expr, vars: &[&String]) -> BTreeSet<String> {
+    let mut fv = expr.free_vars();
+    for v in vars {
+        fv.remove(*v);
+    }
+    fv
 }
 
 /// free文
 ///
+/// 続く式がない`free var`は`free var; un ()`として扱う
+///
 /// ```text
-/// <FREE> := free <E>; <E>
+/// <FREE> := free <VAR>; <E> | free <VAR>
 ///
 /// free var; expr
 /// ```
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
 pub struct FreeExpr {
     pub var: String,
     pub expr: Box<Expr>,
+    pub span: Span,
 }
 
 /// env文
@@ -211,17 +660,61 @@ pub struct FreeExpr {
 ///
 /// env; expr
 /// ```
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
 pub struct EnvExpr {
     pub expr: Box<Expr>,
+    pub span: Span,
+}
+
+/// 型の別名の定義
+///
+/// nameは続く式の中で、型tyの別名として型の代わりに書ける。
+/// 別名はパース後にalias::resolveで元の型に置き換える
+///
+/// ```text
+/// <TYPE> := type <VAR> = <T>; <E>
+///
+/// type name = ty; expr
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
+pub struct TypeDefExpr {
+    pub name: String,
+    pub ty: TypeExpr,
+    pub expr: Box<Expr>,
+    pub span: Span,
+}
+
+/// 逐次実行
+///
+/// expr1の値は捨てられるため、un unit型かaff型でなければならない
+///
+/// ```text
+/// <SEQ> := <E>; <E>
+///
+/// expr1; expr2
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
+pub struct SeqExpr {
+    pub expr1: Box<Expr>,
+    pub expr2: Box<Expr>,
+    pub span: Span,
 }
 
 /// 修飾子付き型
 ///
+/// 型の別名は、修飾子も含めた型全体を表す。
+/// 別名のままの型の修飾子には意味がなく、表示時には別名のみを出力する
+///
+/// 修飾子変数や型変数を量化した型は、primをPrimType::Forallとし、
+/// 量化した変数はqualを含む型全体で有効とする
+///
+/// 型変数の型は、primをPrimType::Varとし、qualを型変数の修飾子の上限とする。
+/// 表示時には型変数の名前のみを出力する
+///
 /// ```text
-/// <QV> := <Q> <VAL>
+/// <T> := forall <PARAM> { , <PARAM> } . <T> | <QT> <P> | <VAR>
 /// ```
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
 pub struct TypeExpr {
     pub qual: Qual,
     pub prim: PrimType,
@@ -229,10 +722,12 @@ pub struct TypeExpr {
 
 impl fmt::Display for TypeExpr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self.qual {
-            Qual::Lin => write!(f, "lin {}", self.prim),
-            Qual::Un => write!(f, "un {}", self.prim),
-            Qual::Aff => write!(f, "aff {}", self.prim),
+        match &self.prim {
+            PrimType::Alias(name) | PrimType::Var(name) => write!(f, "{name}"),
+            PrimType::Forall(params, prim) => {
+                write!(f, "forall {}. {} {}", join_params(params), self.qual, prim)
+            }
+            prim => write!(f, "{} {}", self.qual, prim),
         }
     }
 }
@@ -241,28 +736,73 @@ impl fmt::Display for TypeExpr {
 ///
 /// ```text
 /// <P> := bool |
+///        int |
+///        unit |
 ///        ( <T> * <T> )
+///        ( <T> + <T> )
 ///        ( <T> -> <T> )
 /// ```
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
 pub enum PrimType {
-    Bool,                                // 真偽値型
-    Pair(Box<TypeExpr>, Box<TypeExpr>),  // ペア型
-    Arrow(Box<TypeExpr>, Box<TypeExpr>), // 関数型
+    Forall(Vec<TypeParam>, Box<PrimType>), // 修飾子変数や型変数を量化した型
+    Var(String),                           // 型変数
+    Bool,                                  // 真偽値型
+    Int,                                   // 整数型
+    Unit,                                  // unit型
+    Pair(Box<TypeExpr>, Box<TypeExpr>),    // ペア型
+    Sum(Box<TypeExpr>, Box<TypeExpr>),     // 直和型
+    Arrow(Box<TypeExpr>, Box<TypeExpr>),   // 関数型
+    Alias(String),                         // 解決前の型の別名
 }
 
 impl fmt::Display for PrimType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             PrimType::Bool => write!(f, "bool"),
+            PrimType::Int => write!(f, "int"),
+            PrimType::Unit => write!(f, "unit"),
             PrimType::Pair(t1, t2) => write!(f, "({t1} * {t2})"),
+            PrimType::Sum(t1, t2) => write!(f, "({t1} + {t2})"),
             PrimType::Arrow(t1, t2) => write!(f, "({t1} -> {t2})"),
+            PrimType::Alias(name) | PrimType::Var(name) => write!(f, "{name}"),
+            PrimType::Forall(params, prim) => write!(f, "forall {}. {prim}", join_params(params)),
+        }
+    }
+}
+
+/// forallで量化する変数
+///
+/// boundがNoneの場合は修飾子変数、Someの場合は修飾子がbound以下の型を表す型変数
+///
+/// ```text
+/// <PARAM> := <VAR> | <VAR> : <Q>
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
+pub struct TypeParam {
+    pub name: String,
+    pub bound: Option<Qual>,
+}
+
+impl fmt::Display for TypeParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.bound {
+            Some(bound) => write!(f, "{} : {bound}", self.name),
+            None => write!(f, "{}", self.name),
         }
     }
 }
 
+fn join_params(params: &[TypeParam]) -> String {
+    params
+        .iter()
+        .map(|p| p.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 pub fn parse(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
-    let ast = parse_expr(i);
+    // 末尾の空白とコメントは読み飛ばす
+    let ast = parse_expr(i).and_then(|(i, ast)| Ok((sp0(i)?.0, ast)));
     match ast {
         Ok((i, ast)) => match i {
             "" => Ok((i, ast)),
@@ -277,88 +817,283 @@ pub fn parse(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
     }
 }
 
-
+/// 式をパース。
+///
+/// 返り値の式の位置情報は入力`i`の先頭からのバイトオフセットとなる。
 pub fn parse_expr(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
-    let (i, _) = multispace0(i)?;
-    let (i, val) = alt((alpha1, tag("(")))(i)?;
+    let len = i.len();
+    let (rest, mut e) = parse_expr_rel(i)?;
+    // 残りの入力の長さから、先頭からのオフセットに変換
+    e.map_span(&|s| Span {
+        start: len - s.start,
+        end: len - s.end,
+    });
+    Ok((rest, e))
+}
+
+/// 式をパース。`e1; e2`のように続く場合は逐次実行とする。
+///
+/// 位置情報は残りの入力の長さとして記録し、parse_exprでオフセットに変換する。
+fn parse_expr_rel(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    let (i, _) = sp0(i)?;
+    let start = i;
+    let (i, e1) = parse_term(i)?;
+
+    // ; <E>
+    let (i, e2) = opt(pair(delimited(sp0, char(';'), sp0), parse_expr_rel))(i)?;
+    match e2 {
+        Some((_, e2)) => Ok((
+            i,
+            Expr::Seq(SeqExpr {
+                expr1: Box::new(e1),
+                expr2: Box::new(e2),
+                span: span(start, i),
+            }),
+        )),
+        None => Ok((i, e1)),
+    }
+}
+
+/// 逐次実行を含まない式をパース。
+///
+/// letやdefの束縛する値のように、後ろに;が続く位置で用いる。
+fn parse_term(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    let (i, _) = sp0(i)?;
+    let start = i;
+    let (i, val) = alt((identifier, tag("(")))(i)?;
 
     match val {
-        "let" => parse_let(i),
-        "if" => parse_if(i),
-        "split" => parse_split(i),
-        "free" => parse_free(i),
-        "lin" => parse_qval(Qual::Lin, i),
-        "un" => parse_qval(Qual::Un, i),
-        "aff" => parse_qval(Qual::Aff, i),
-        "(" => parse_app(i),
-        "def" => parse_def(i),
-        "env" => parse_env(i),
-        _ => Ok((i, Expr::Var(val.to_string()))),
+        "let" => parse_let(start, i),
+        "if" => parse_if(start, i),
+        "split" => parse_split(start, i),
+        "case" => parse_case(start, i),
+        "free" => parse_free(start, i),
+        "lin" => parse_qval(Qual::Lin, start, i),
+        "un" => parse_qval(Qual::Un, start, i),
+        "aff" => parse_qval(Qual::Aff, start, i),
+        "(" => parse_app(start, i),
+        "def" => parse_def(start, i),
+        "env" => parse_env(start, i),
+        "type" => parse_typedef(start, i),
+        _ => {
+            let (i, var) = parse_var(start)?;
+            Ok((
+                i,
+                Expr::Var(VarExpr {
+                    var,
+                    span: span(start, i),
+                }),
+            ))
+        }
     }
 }
 
-/// 関数適用をパース。
-fn parse_app(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
-    let (i, _) = multispace0(i)?;
-    let (i, e1) = parse_expr(i)?; // 適用する関数
+/// 予約語。変数名には使えない
+const KEYWORDS: &[&str] = &[
+    "let", "rec", "if", "else", "split", "as", "case", "of", "inl", "inr", "free", "fn", "true",
+    "false", "lin", "un", "aff", "def", "env", "type", "forall", "bool", "int", "unit",
+];
+
+/// 識別子をパース。`[a-zA-Z_][a-zA-Z0-9_']*`
+fn identifier(i: &str) -> IResult<&str, &str, VerboseError<&str>> {
+    recognize(pair(
+        alt((alpha1, tag("_"))),
+        many0(alt((alphanumeric1, tag("_"), tag("'")))),
+    ))(i)
+}
 
-    let (i, _) = multispace1(i)?;
+/// 予約語kwをパース。`linear`の`lin`のように、識別子の一部には一致しない
+fn keyword<'a>(
+    kw: &'static str,
+) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str, VerboseError<&'a str>> {
+    verify(identifier, move |s: &str| s == kw)
+}
 
-    let (i, e2) = parse_expr(i)?; // 引数
+/// 0個以上の空白とコメントを読み飛ばす
+pub(crate) fn sp0(i: &str) -> IResult<&str, (), VerboseError<&str>> {
+    value((), many0(alt((multispace1, comment))))(i)
+}
 
-    let (i, _) = multispace0(i)?;
-    let (i, _) = char(')')(i)?;
+/// 1個以上の空白とコメントを読み飛ばす
+fn sp1(i: &str) -> IResult<&str, (), VerboseError<&str>> {
+    value((), many1(alt((multispace1, comment))))(i)
+}
 
-    Ok((
-        i,
-        Expr::App(AppExpr {
-            expr1: Box::new(e1),
+/// コメントをパース。`//`から行末までと、`/*`から`*/`までをコメントとする
+fn comment(i: &str) -> IResult<&str, &str, VerboseError<&str>> {
+    alt((
+        recognize(pair(tag("//"), not_line_ending)),
+        recognize(delimited(tag("/*"), take_until("*/"), tag("*/"))),
+    ))(i)
+}
+
+/// ソースコード中のコメントを出現順に取得
+///
+/// Linzinには文字列リテラルがないため、`//`と`/*`は常にコメントの開始となる
+pub fn comments(src: &str) -> Vec<Comment> {
+    let mut ret = Vec::new();
+    let mut i = src;
+    while !i.is_empty() {
+        if let Ok((rest, c)) = comment(i) {
+            ret.push(Comment {
+                text: c.trim_end().to_string(),
+                span: Span {
+                    start: src.len() - i.len(),
+                    end: src.len() - rest.len(),
+                },
+            });
+            i = rest;
+        } else {
+            let mut chars = i.chars();
+            chars.next();
+            i = chars.as_str();
+        }
+    }
+    ret
+}
+
+/// 開始時と終了時の残りの入力から位置情報を作成
+fn span(start: &str, end: &str) -> Span {
+    Span {
+        start: start.len(),
+        end: end.len(),
+    }
+}
+
+/// 関数適用か二項演算をパース。
+fn parse_app<'a>(start: &'a str, i: &'a str) -> IResult<&'a str, Expr, VerboseError<&'a str>> {
+    let (i, _) = sp0(i)?;
+    // ()はun ()とする
+    if let (i, Some(_)) = opt(char(')'))(i)? {
+        return Ok((
+            i,
+            Expr::QVal(QValExpr {
+                qual: Qual::Un,
+                val: ValExpr::Unit,
+                span: span(start, i),
+            }),
+        ));
+    }
+
+    let (i, e1) = parse_expr_rel(i)?; // 適用する関数か、左辺
+
+    let (i, _) = sp0(i)?;
+
+    // 演算子が続く場合は二項演算
+    if let (i, Some(op)) = opt(parse_binop)(i)? {
+        let (i, _) = sp0(i)?;
+        let (i, e2) = parse_expr_rel(i)?; // 右辺
+        let (i, _) = sp0(i)?;
+        let (i, _) = char(')')(i)?;
+
+        return Ok((
+            i,
+            Expr::BinOp(BinOpExpr {
+                op,
+                expr1: Box::new(e1),
+                expr2: Box::new(e2),
+                span: span(start, i),
+            }),
+        ));
+    }
+
+    // 引数が複数ある場合は、(((f a) b) c)のように左から順に適用する
+    let mut e = e1;
+    let mut i = i;
+    loop {
+        let (rest, e2) = parse_expr_rel(i)?; // 引数
+        e = Expr::App(AppExpr {
+            expr1: Box::new(e),
             expr2: Box::new(e2),
-        }),
-    ))
+            span: span(start, rest),
+        });
+
+        let (rest, _) = sp0(rest)?;
+        if let (rest, Some(_)) = opt(char(')'))(rest)? {
+            if let Expr::App(app) = &mut e {
+                app.span = span(start, rest);
+            }
+            return Ok((rest, e));
+        }
+        i = rest;
+    }
 }
 
-/// free文をパース。
-fn parse_free(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
-    let (i, _) = multispace1(i)?;
-    let (i, var) = alpha1(i)?; // 解放する変数
-    let (i, _) = multispace0(i)?;
-    let (i, _) = char(';')(i)?;
+/// 二項演算子をパース。
+fn parse_binop(i: &str) -> IResult<&str, BinOp, VerboseError<&str>> {
+    let (i, op) = alt((
+        tag("<="),
+        tag("=="),
+        tag("!="),
+        tag("<"),
+        tag("+"),
+        tag("-"),
+        tag("*"),
+        tag("/"),
+        tag("%"),
+    ))(i)?;
+    let op = match op {
+        "<=" => BinOp::Le,
+        "==" => BinOp::Eq,
+        "!=" => BinOp::Ne,
+        "<" => BinOp::Lt,
+        "+" => BinOp::Add,
+        "-" => BinOp::Sub,
+        "*" => BinOp::Mul,
+        "/" => BinOp::Div,
+        _ => BinOp::Mod,
+    };
+    Ok((i, op))
+}
 
-    let (i, e) = parse_expr(i)?; // 続けて実行する式
+/// free文をパース。
+fn parse_free<'a>(start: &'a str, i: &'a str) -> IResult<&'a str, Expr, VerboseError<&'a str>> {
+    let (i, _) = sp1(i)?;
+    let (i, var) = parse_var(i)?; // 解放する変数
+    let (i, _) = sp0(i)?;
+
+    let (i, e) = match opt(char(';'))(i)? {
+        (i, Some(_)) => parse_expr_rel(i)?, // 続けて実行する式
+        // 続く式がない場合はun ()
+        (i, None) => (
+            i,
+            Expr::QVal(QValExpr {
+                qual: Qual::Un,
+                val: ValExpr::Unit,
+                span: span(i, i),
+            }),
+        ),
+    };
     Ok((
         i,
         Expr::Free(FreeExpr {
-            var: var.to_string(),
+            var,
             expr: Box::new(e),
+            span: span(start, i),
         }),
     ))
 }
 
 /// split式をパース。
-fn parse_split(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
-    let (i, _) = multispace1(i)?;
-    let (i, e1) = parse_expr(i)?; // 分解するペア
+fn parse_split<'a>(start: &'a str, i: &'a str) -> IResult<&'a str, Expr, VerboseError<&'a str>> {
+    let (i, _) = sp1(i)?;
+    let (i, e1) = parse_expr_rel(i)?; // 分解するペア
 
-    let (i, _) = multispace1(i)?;
-    let (i, _) = tag("as")(i)?;
-    let (i, _) = multispace1(i)?;
+    let (i, _) = sp1(i)?;
+    let (i, _) = keyword("as")(i)?;
+    let (i, _) = sp1(i)?;
 
     let (i, v1) = parse_var(i)?; // 一つめの変数
 
-    let (i, _) = multispace0(i)?;
+    let (i, _) = sp0(i)?;
     let (i, _) = char(',')(i)?;
-    let (i, _) = multispace0(i)?;
+    let (i, _) = sp0(i)?;
 
     let (i, v2) = parse_var(i)?; // 二つめの変数
-    let (i, _) = multispace0(i)?;
+    let (i, _) = sp0(i)?;
 
     // { <E> }というように、波括弧で囲まれた式をパース
-    let (i, e2) = delimited(
-        char('{'),
-        delimited(multispace0, parse_expr, multispace0),
-        char('}'),
-    )(i)?;
+    let (i, e2) = delimited(char('{'), delimited(sp0, parse_expr_rel, sp0), char('}'))(i)?;
 
     Ok((
         i,
@@ -367,33 +1102,68 @@ fn parse_split(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
             left: v1,
             right: v2,
             body: Box::new(e2),
+            tys: None,
+            span: span(start, i),
+        }),
+    ))
+}
+
+/// case式をパース。
+fn parse_case<'a>(start: &'a str, i: &'a str) -> IResult<&'a str, Expr, VerboseError<&'a str>> {
+    let (i, _) = sp1(i)?;
+    let (i, e1) = parse_expr_rel(i)?; // 分解する直和
+
+    let (i, _) = sp1(i)?;
+    let (i, _) = keyword("of")(i)?;
+    let (i, _) = sp1(i)?;
+
+    // inl <VAR> { <E> }
+    let (i, _) = keyword("inl")(i)?;
+    let (i, _) = sp1(i)?;
+    let (i, v1) = parse_var(i)?;
+    let (i, _) = sp0(i)?;
+    let (i, e2) = delimited(char('{'), delimited(sp0, parse_expr_rel, sp0), char('}'))(i)?;
+
+    let (i, _) = sp0(i)?;
+    let (i, _) = char('|')(i)?;
+    let (i, _) = sp0(i)?;
+
+    // inr <VAR> { <E> }
+    let (i, _) = keyword("inr")(i)?;
+    let (i, _) = sp1(i)?;
+    let (i, v2) = parse_var(i)?;
+    let (i, _) = sp0(i)?;
+    let (i, e3) = delimited(char('{'), delimited(sp0, parse_expr_rel, sp0), char('}'))(i)?;
+
+    Ok((
+        i,
+        Expr::Case(CaseExpr {
+            expr: Box::new(e1),
+            inl_var: v1,
+            inl_expr: Box::new(e2),
+            inr_var: v2,
+            inr_expr: Box::new(e3),
+            tys: None,
+            span: span(start, i),
         }),
     ))
 }
 
 /// if式をパース。
-fn parse_if(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
-    let (i, _) = multispace1(i)?;
-    let (i, e1) = parse_expr(i)?; // 条件
-    let (i, _) = multispace0(i)?;
+fn parse_if<'a>(start: &'a str, i: &'a str) -> IResult<&'a str, Expr, VerboseError<&'a str>> {
+    let (i, _) = sp1(i)?;
+    let (i, e1) = parse_expr_rel(i)?; // 条件
+    let (i, _) = sp0(i)?;
 
     // 条件が真の時に実行する式
-    let (i, e2) = delimited(
-        char('{'),
-        delimited(multispace0, parse_expr, multispace0),
-        char('}'),
-    )(i)?;
+    let (i, e2) = delimited(char('{'), delimited(sp0, parse_expr_rel, sp0), char('}'))(i)?;
 
-    let (i, _) = multispace0(i)?;
-    let (i, _) = tag("else")(i)?;
-    let (i, _) = multispace0(i)?;
+    let (i, _) = sp0(i)?;
+    let (i, _) = keyword("else")(i)?;
+    let (i, _) = sp0(i)?;
 
     // 条件が偽の時に実行する式
-    let (i, e3) = delimited(
-        char('{'),
-        delimited(multispace0, parse_expr, multispace0),
-        char('}'),
-    )(i)?;
+    let (i, e3) = delimited(char('{'), delimited(sp0, parse_expr_rel, sp0), char('}'))(i)?;
 
     Ok((
         i,
@@ -401,89 +1171,131 @@ fn parse_if(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
             cond_expr: Box::new(e1),
             then_expr: Box::new(e2),
             else_expr: Box::new(e3),
+            span: span(start, i),
         }),
     ))
 }
 
 /// let式をパース。
-fn parse_let(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
-    let (i, _) = multispace1(i)?;
+fn parse_let<'a>(start: &'a str, i: &'a str) -> IResult<&'a str, Expr, VerboseError<&'a str>> {
+    let (i, _) = sp1(i)?;
 
-    let (i, var) = parse_var(i)?; // 束縛する変数
+    // 再帰関数の束縛
+    let (i, rec) = opt(pair(keyword("rec"), sp1))(i)?;
 
-    let (i, _) = multispace0(i)?;
-    let (i, _) = char(':')(i)?;
-    let (i, _) = multispace0(i)?;
+    let (i, var) = parse_var(i)?; // 束縛する変数
 
-    let (i, ty) = parse_type(i)?; // 変数の型
+    let (i, _) = sp0(i)?;
+    let (i, ty) = opt(parse_annotation)(i)?; // 変数の型
 
-    let (i, _) = multispace0(i)?;
     let (i, _) = char('=')(i)?;
-    let (i, _) = multispace0(i)?;
+    let (i, _) = sp0(i)?;
 
-    let (i, e1) = parse_expr(i)?; // 変数の値
-    let (i, _) = multispace0(i)?;
+    let (i, e1) = parse_term(i)?; // 変数の値
+    let (i, _) = sp0(i)?;
 
     let (i, _) = char(';')(i)?;
-    let (i, e2) = parse_expr(i)?; // 実行する式
+    let (i, e2) = parse_expr_rel(i)?; // 実行する式
 
     Ok((
         i,
         Expr::Let(LetExpr {
+            rec: rec.is_some(),
             var,
             ty,
+            inferred: None,
             expr1: Box::new(e1),
             expr2: Box::new(e2),
+            span: span(start, i),
         }),
     ))
 }
 
+/// 型注釈をパース。: <T>
+fn parse_annotation(i: &str) -> IResult<&str, TypeExpr, VerboseError<&str>> {
+    let (i, _) = char(':')(i)?;
+    let (i, _) = sp0(i)?;
+    let (i, ty) = parse_type(i)?;
+    let (i, _) = sp0(i)?;
+    Ok((i, ty))
+}
+
 /// ペアをパース。
 fn parse_pair(i: &str) -> IResult<&str, ValExpr, VerboseError<&str>> {
-    let (i, _) = multispace0(i)?;
+    let (i, _) = sp0(i)?;
 
-    let (i, v1) = parse_expr(i)?; // 一つめの値
+    let (i, v1) = parse_expr_rel(i)?; // 一つめの値
 
-    let (i, _) = multispace0(i)?;
+    let (i, _) = sp0(i)?;
     let (i, _) = char(',')(i)?;
-    let (i, _) = multispace0(i)?;
+    let (i, _) = sp0(i)?;
 
-    let (i, v2) = parse_expr(i)?; // 二つめの値
+    let (i, v2) = parse_expr_rel(i)?; // 二つめの値
 
-    let (i, _) = multispace0(i)?;
+    let (i, _) = sp0(i)?;
     let (i, _) = char('>')(i)?; // 閉じ括弧
 
     Ok((i, ValExpr::Pair(Box::new(v1), Box::new(v2))))
 }
 
-/// linとun修飾子をパース。
+/// 型の修飾子をパース。lin, un, affか修飾子変数
 fn parse_qual(i: &str) -> IResult<&str, Qual, VerboseError<&str>> {
-    let (i, val) = alt((tag("lin"), tag("un"), tag("aff")))(i)?;
+    let (i, val) = identifier(i)?;
     match val {
         "lin" => Ok((i, Qual::Lin)),
         "un" => Ok((i, Qual::Un)),
-        _ => Ok((i, Qual::Aff)),
+        "aff" => Ok((i, Qual::Aff)),
+        _ if KEYWORDS.contains(&val) => Err(nom::Err::Error(VerboseError::from_error_kind(
+            i,
+            ErrorKind::Tag,
+        ))),
+        _ => Ok((i, Qual::Var(val.to_string()))),
     }
 }
 
-/// 関数をパース。
+/// 関数をパース
+///
+/// 複数の引数を持つ関数の二つめ以降の引数はrestとし、関数の入れ子への変換は
+/// 引数の型の別名や型変数を解決した後にalias::resolveで行う
 fn parse_fn(i: &str) -> IResult<&str, ValExpr, VerboseError<&str>> {
-    let (i, _) = multispace1(i)?;
-    let (i, var) = parse_var(i)?; // 引数
-
-    let (i, _) = multispace0(i)?;
-    let (i, _) = char(':')(i)?;
-    let (i, _) = multispace0(i)?;
-
-    let (i, ty) = parse_type(i)?; // 引数の型
-    let (i, _) = multispace0(i)?;
+    let (i, _) = sp1(i)?;
+
+    // <VAR> : <T>, <VAR> : <T>, ...
+    let mut params = Vec::new();
+    let mut i = i;
+    loop {
+        let start = i;
+        let (rest, var) = parse_var(i)?; // 引数
+
+        let (rest, _) = sp0(rest)?;
+        let (rest, _) = char(':')(rest)?;
+        let (rest, _) = sp0(rest)?;
+
+        let (rest, ty) = parse_type(rest)?; // 引数の型
+        let (rest, _) = sp0(rest)?;
+        params.push((start, var, ty));
+
+        match opt(pair(char(','), sp0))(rest)? {
+            (rest, Some(_)) => i = rest,
+            (rest, None) => {
+                i = rest;
+                break;
+            }
+        }
+    }
 
     // { <E> }というように、波括弧で囲まれた式をパース
-    let (i, expr) = delimited(
-        char('{'),
-        delimited(multispace0, parse_expr, multispace0),
-        char('}'),
-    )(i)?;
+    let (i, expr) = delimited(char('{'), delimited(sp0, parse_expr_rel, sp0), char('}'))(i)?;
+
+    let (_, var, ty) = params.remove(0);
+    let rest = params
+        .into_iter()
+        .map(|(start, var, ty)| FnParam {
+            var,
+            ty,
+            span: span(start, i),
+        })
+        .collect();
 
     Ok((
         i,
@@ -491,41 +1303,171 @@ fn parse_fn(i: &str) -> IResult<&str, ValExpr, VerboseError<&str>> {
             var,
             ty,
             expr: Box::new(expr),
+            rest,
         }),
     ))
 }
 
-/// 真偽値、関数、ペアの値をパース。
+/// 直和型への注入をパース。
+fn parse_inj(i: &str) -> IResult<&str, InjExpr, VerboseError<&str>> {
+    let (i, _) = sp0(i)?;
+    let (i, _) = char('(')(i)?;
+    let (i, _) = sp0(i)?;
+    let (i, t1) = parse_type(i)?; // 左側の型
+    let (i, _) = sp0(i)?;
+    let (i, _) = char('+')(i)?;
+    let (i, _) = sp0(i)?;
+    let (i, t2) = parse_type(i)?; // 右側の型
+    let (i, _) = sp0(i)?;
+    let (i, _) = char(')')(i)?;
+
+    let (i, _) = sp0(i)?;
+    let (i, e) = parse_term(i)?; // 値
+
+    Ok((
+        i,
+        InjExpr {
+            left_ty: t1,
+            right_ty: t2,
+            expr: Box::new(e),
+        },
+    ))
+}
+
+/// 真偽値、整数、関数、ペア、直和の値をパース。
 fn parse_val(i: &str) -> IResult<&str, ValExpr, VerboseError<&str>> {
-    let (i, val) = alt((tag("fn"), tag("true"), tag("false"), tag("<")))(i)?;
+    let (i, val) = alt((
+        keyword("fn"),
+        keyword("true"),
+        keyword("false"),
+        tag("<"),
+        tag("()"),
+        keyword("inl"),
+        keyword("inr"),
+        recognize(pair(opt(char('-')), digit1)),
+    ))(i)?;
     match val {
         "fn" => parse_fn(i),
         "true" => Ok((i, ValExpr::Bool(true))),
         "false" => Ok((i, ValExpr::Bool(false))),
         "<" => parse_pair(i),
-        _ => unreachable!(),
+        "()" => Ok((i, ValExpr::Unit)),
+        "inl" => {
+            let (i, e) = parse_inj(i)?;
+            Ok((i, ValExpr::Inl(e)))
+        }
+        "inr" => {
+            let (i, e) = parse_inj(i)?;
+            Ok((i, ValExpr::Inr(e)))
+        }
+        _ => match val.parse() {
+            Ok(n) => Ok((i, ValExpr::Int(n))),
+            // i64に収まらない
+            Err(_) => Err(nom::Err::Failure(VerboseError::from_error_kind(
+                val,
+                ErrorKind::Digit,
+            ))),
+        },
     }
 }
 
 /// 修飾子付き値をパース。
-fn parse_qval(q: Qual, i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
-    let (i, _) = multispace1(i)?;
+fn parse_qval<'a>(
+    q: Qual,
+    start: &'a str,
+    i: &'a str,
+) -> IResult<&'a str, Expr, VerboseError<&'a str>> {
+    let (i, _) = sp1(i)?;
     let (i, v) = parse_val(i)?;
 
-    Ok((i, Expr::QVal(QValExpr { qual: q, val: v })))
+    Ok((
+        i,
+        Expr::QVal(QValExpr {
+            qual: q,
+            val: v,
+            span: span(start, i),
+        }),
+    ))
 }
 
-/// 変数をパース。変数は1文字以上のアルファベットから成り立つ。
+/// 変数をパース。予約語は変数名に使えない。
 fn parse_var(i: &str) -> IResult<&str, String, VerboseError<&str>> {
-    let (i, v) = alpha1(i)?;
-    Ok((i, v.to_string()))
+    let (rest, v) = identifier(i)?;
+    if KEYWORDS.contains(&v) {
+        return Err(nom::Err::Failure(VerboseError {
+            errors: vec![(
+                i,
+                VerboseErrorKind::Context("reserved word used as a variable name"),
+            )],
+        }));
+    }
+    Ok((rest, v.to_string()))
 }
 
-/// 真偽値、関数、ペア型をパース。
-fn parse_type(i: &str) -> IResult<&str, TypeExpr, VerboseError<&str>> {
+/// 型をパース。
+pub(crate) fn parse_type(i: &str) -> IResult<&str, TypeExpr, VerboseError<&str>> {
+    // forall q, a : lin, ... . <T>
+    if let (i, Some(_)) = opt(pair(keyword("forall"), sp1))(i)? {
+        let (i, mut params) = separated_list1(delimited(sp0, char(','), sp0), parse_type_param)(i)?;
+        let (i, _) = delimited(sp0, char('.'), sp0)(i)?;
+        let (i, ty) = parse_type(i)?;
+
+        // forall q. forall p. <T>はforall q, p. <T>とする
+        let prim = match ty.prim {
+            PrimType::Forall(inner, prim) => {
+                params.extend(inner);
+                prim
+            }
+            prim => Box::new(prim),
+        };
+        return Ok((
+            i,
+            TypeExpr {
+                qual: ty.qual,
+                prim: PrimType::Forall(params, prim),
+            },
+        ));
+    }
+
+    match parse_qual_type(i) {
+        Err(nom::Err::Error(_)) => (),
+        ret => return ret,
+    }
+
+    // 型の別名。修飾子は別名を解決する際に定義の修飾子となる
+    let (i, name) = verify(identifier, |s: &str| !KEYWORDS.contains(&s))(i)?;
+    Ok((
+        i,
+        TypeExpr {
+            qual: Qual::Un,
+            prim: PrimType::Alias(name.to_string()),
+        },
+    ))
+}
+
+/// forallで量化する変数をパース。<VAR> | <VAR> : <Q>
+///
+/// 型変数の修飾子の上限には、修飾子変数は書けない
+fn parse_type_param(i: &str) -> IResult<&str, TypeParam, VerboseError<&str>> {
+    let (i, name) = verify(identifier, |s: &str| !KEYWORDS.contains(&s))(i)?;
+    let (i, bound) = opt(preceded(
+        delimited(sp0, char(':'), sp0),
+        verify(parse_qual, |q: &Qual| !matches!(q, Qual::Var(_))),
+    ))(i)?;
+    Ok((
+        i,
+        TypeParam {
+            name: name.to_string(),
+            bound,
+        },
+    ))
+}
+
+/// 修飾子付きの型をパース。<QT> <P>
+fn parse_qual_type(i: &str) -> IResult<&str, TypeExpr, VerboseError<&str>> {
     let (i, q) = parse_qual(i)?; // 修飾子
-    let (i, _) = multispace1(i)?;
-    let (i, val) = alt((tag("bool"), tag("(")))(i)?;
+    let (i, _) = sp1(i)?;
+    let (i, val) = alt((keyword("bool"), keyword("int"), keyword("unit"), tag("(")))(i)?;
     if val == "bool" {
         // bool型
         Ok((
@@ -535,19 +1477,37 @@ fn parse_type(i: &str) -> IResult<&str, TypeExpr, VerboseError<&str>> {
                 prim: PrimType::Bool,
             },
         ))
+    } else if val == "int" {
+        // int型
+        Ok((
+            i,
+            TypeExpr {
+                qual: q,
+                prim: PrimType::Int,
+            },
+        ))
+    } else if val == "unit" {
+        // unit型
+        Ok((
+            i,
+            TypeExpr {
+                qual: q,
+                prim: PrimType::Unit,
+            },
+        ))
     } else {
         // 関数型かペア型
-        let (i, _) = multispace0(i)?;
+        let (i, _) = sp0(i)?;
         let (i, t1) = parse_type(i)?; // 一つめの型
-        let (i, _) = multispace0(i)?;
+        let (i, _) = sp0(i)?;
 
-        // ->か*をパース
-        // ->の場合は関数型で、場合はペア型
-        let (i, op) = alt((tag("*"), tag("->")))(i)?;
+        // ->か*か+をパース
+        // ->の場合は関数型で、*の場合はペア型、+の場合は直和型
+        let (i, op) = alt((tag("*"), tag("+"), tag("->")))(i)?;
 
-        let (i, _) = multispace0(i)?;
+        let (i, _) = sp0(i)?;
         let (i, t2) = parse_type(i)?; // 二つめの型
-        let (i, _) = multispace0(i)?;
+        let (i, _) = sp0(i)?;
 
         let (i, _) = char(')')(i)?;
 
@@ -555,10 +1515,10 @@ fn parse_type(i: &str) -> IResult<&str, TypeExpr, VerboseError<&str>> {
             i,
             TypeExpr {
                 qual: q,
-                prim: if op == "*" {
-                    PrimType::Pair(Box::new(t1), Box::new(t2))
-                } else {
-                    PrimType::Arrow(Box::new(t1), Box::new(t2))
+                prim: match op {
+                    "*" => PrimType::Pair(Box::new(t1), Box::new(t2)),
+                    "+" => PrimType::Sum(Box::new(t1), Box::new(t2)),
+                    _ => PrimType::Arrow(Box::new(t1), Box::new(t2)),
                 },
             },
         ))
@@ -567,23 +1527,19 @@ fn parse_type(i: &str) -> IResult<&str, TypeExpr, VerboseError<&str>> {
 
 /// defをパース
 /// def <VAR> : <T> = <E>;
-fn parse_def(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
-    let (i, _) = multispace1(i)?;
+fn parse_def<'a>(start: &'a str, i: &'a str) -> IResult<&'a str, Expr, VerboseError<&'a str>> {
+    let (i, _) = sp1(i)?;
 
     let (i, var) = parse_var(i)?; // 束縛する変数
 
-    let (i, _) = multispace0(i)?;
-    let (i, _) = char(':')(i)?;
-    let (i, _) = multispace0(i)?;
-
-    let (i, ty) = parse_type(i)?; // 変数の型
+    let (i, _) = sp0(i)?;
+    let (i, ty) = opt(parse_annotation)(i)?; // 変数の型
 
-    let (i, _) = multispace0(i)?;
     let (i, _) = char('=')(i)?;
-    let (i, _) = multispace0(i)?;
+    let (i, _) = sp0(i)?;
 
-    let (i, e1) = parse_expr(i)?; // 変数の値
-    let (i, _) = multispace0(i)?;
+    let (i, e1) = parse_term(i)?; // 変数の値
+    let (i, _) = sp0(i)?;
 
     let (i, _) = char(';')(i)?;
 
@@ -593,16 +1549,250 @@ fn parse_def(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
             var,
             ty,
             expr: Box::new(e1),
+            span: span(start, i),
+        }),
+    ))
+}
+
+/// 型の別名の定義をパース
+/// type <VAR> = <T>; <E>
+fn parse_typedef<'a>(start: &'a str, i: &'a str) -> IResult<&'a str, Expr, VerboseError<&'a str>> {
+    let (i, _) = sp1(i)?;
+    let (i, name) = parse_var(i)?; // 別名
+
+    let (i, _) = sp0(i)?;
+    let (i, _) = char('=')(i)?;
+    let (i, _) = sp0(i)?;
+
+    let (i, ty) = parse_type(i)?; // 別名の表す型
+    let (i, _) = sp0(i)?;
+
+    let (i, _) = char(';')(i)?;
+    let (i, e) = parse_expr_rel(i)?; // 別名を使う式
+
+    Ok((
+        i,
+        Expr::Type(TypeDefExpr {
+            name,
+            ty,
+            expr: Box::new(e),
+            span: span(start, i),
         }),
     ))
 }
 
 /// envをパース
 /// env; <E>
-fn parse_env(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+fn parse_env<'a>(start: &'a str, i: &'a str) -> IResult<&'a str, Expr, VerboseError<&'a str>> {
     let (i, _) = char(';')(i)?;
-    let (i, _) = multispace0(i)?;
-    let (i, e) = parse_expr(i)?; // 変数の値
-    let (i, _) = multispace0(i)?;
-    Ok((i, Expr::Env(EnvExpr { expr: Box::new(e) })))
+    let (i, _) = sp0(i)?;
+    let (i, e) = parse_expr_rel(i)?; // 変数の値
+    let (i, _) = sp0(i)?;
+    Ok((
+        i,
+        Expr::Env(EnvExpr {
+            expr: Box::new(e),
+            span: span(start, i),
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::*;
+
+    fn var(input: &str) -> Option<String> {
+        match parse(input) {
+            Ok((_, Expr::Var(e))) => Some(e.var),
+            _ => None,
+        }
+    }
+
+    // 位置情報を除いてパース
+    fn parse_without_span(input: &str) -> Expr {
+        let (_, mut expr) = parse(input).unwrap();
+        expr.map_span(&|_| Span::default());
+        expr
+    }
+
+    #[test]
+    fn test_multi_arg() {
+        // 複数の引数への適用は左から順に適用する
+        assert_eq!(
+            parse_without_span("((f a) b)"),
+            parse_without_span("(f a b)")
+        );
+        assert_eq!(
+            parse_without_span("(((f a) (g b)) un 1)"),
+            parse_without_span("( f a (g b)\n un 1 )")
+        );
+
+        // 複数の引数を持つ関数の二つめ以降の引数はrestに保持する
+        match parse_without_span("un fn x : lin bool, y : un bool,z : aff int { x }") {
+            Expr::QVal(QValExpr {
+                val: ValExpr::Fun(e),
+                ..
+            }) => {
+                assert_eq!("x", e.var);
+                let vars: Vec<&str> = e.rest.iter().map(|p| p.var.as_str()).collect();
+                assert_eq!(vec!["y", "z"], vars);
+            }
+            _ => panic!("function expected"),
+        }
+
+        // 入れ子の関数適用の位置情報は、開き括弧から各引数の終わりまで
+        let (_, expr) = parse_expr("(f a b)").unwrap();
+        match expr {
+            Expr::App(e) => {
+                assert_eq!(Span { start: 0, end: 7 }, e.span);
+                assert_eq!(Span { start: 0, end: 4 }, e.expr1.span());
+            }
+            _ => panic!("application expected"),
+        }
+
+        assert!(parse("(f)").is_err());
+        assert!(parse("(f a b").is_err());
+        assert!(parse("un fn x : un bool, { x }").is_err());
+    }
+
+    #[test]
+    fn test_inj() {
+        // 直和の値は続く`;`を取り込まない
+        let input = "let s : lin (lin bool + un int) = lin inl (lin bool + un int) lin true;
+            case s of inl a { a } | inr b { lin false }";
+        match parse_without_span(input) {
+            Expr::Let(e) => {
+                assert!(matches!(
+                    *e.expr1,
+                    Expr::QVal(QValExpr {
+                        val: ValExpr::Inl(_),
+                        ..
+                    })
+                ));
+                assert!(matches!(*e.expr2, Expr::Case(_)));
+            }
+            _ => panic!("let expression expected"),
+        }
+        // 直和の値の後の`;`は逐次実行とする
+        assert!(matches!(
+            parse_without_span("lin inl (un bool + un int) un true; un 1"),
+            Expr::Seq(_)
+        ));
+    }
+
+    #[test]
+    fn test_forall() {
+        let (i, t) = parse_type("forall q. un (q bool -> q (q bool * un int))").unwrap();
+        assert_eq!("", i);
+        assert_eq!(
+            "forall q. un (q bool -> q (q bool * un int))",
+            t.to_string()
+        );
+        match t.prim {
+            PrimType::Forall(params, prim) => {
+                assert_eq!(
+                    vec![TypeParam {
+                        name: "q".to_string(),
+                        bound: None
+                    }],
+                    params
+                );
+                assert!(matches!(*prim, PrimType::Arrow(_, _)));
+            }
+            _ => panic!("forall type expected"),
+        }
+
+        // 入れ子のforallはまとめる
+        let (_, t) =
+            parse_type("forall q, p.forall r . lin (q bool -> p (r int * un unit))").unwrap();
+        assert_eq!(
+            "forall q, p, r. lin (q bool -> p (r int * un unit))",
+            t.to_string()
+        );
+
+        // 値の修飾子には修飾子変数を書けない
+        assert!(parse("let x : q bool = q true; x").is_err());
+        assert!(parse_type("forall . un bool").is_err());
+        assert!(parse_type("forall lin. un bool").is_err());
+
+        // 修飾子の上限を持つ型変数
+        let (_, t) = parse_type("forall a : lin, b:un, q. un (lin (a * b) -> q (b * a))").unwrap();
+        assert_eq!(
+            "forall a : lin, b : un, q. un (lin (a * b) -> q (b * a))",
+            t.to_string()
+        );
+        match t.prim {
+            PrimType::Forall(params, _) => {
+                assert_eq!(Some(Qual::Lin), params[0].bound);
+                assert_eq!(Some(Qual::Un), params[1].bound);
+                assert_eq!(None, params[2].bound);
+            }
+            _ => panic!("forall type expected"),
+        }
+        // 上限に修飾子変数は書けない
+        assert!(parse_type("forall q, a : q. un (a -> a)").is_err());
+    }
+
+    #[test]
+    fn test_identifier() {
+        for v in [
+            "x1", "my_var", "_tmp", "x'", "linear", "iffy", "unit_", "Fn",
+        ] {
+            assert_eq!(Some(v.to_string()), var(v));
+        }
+
+        // 予約語と同じ接頭辞を持つ変数
+        let (_, expr) =
+            parse("let linear : lin bool = lin true; if linear { iffy } else { iffy }").unwrap();
+        match expr {
+            Expr::Let(e) => {
+                assert_eq!("linear", e.var);
+                assert!(matches!(*e.expr2, Expr::If(_)));
+            }
+            _ => panic!("let expression expected"),
+        }
+
+        // 予約語は変数名に使えない
+        for input in [
+            "if",
+            "let if : un bool = un true; if",
+            "else",
+            "free lin",
+            "un truex",
+        ] {
+            assert!(parse(input).is_err(), "{input}");
+        }
+        match parse("let if : un bool = un true; if") {
+            Err(nom::Err::Failure(e)) => assert_eq!(
+                VerboseErrorKind::Context("reserved word used as a variable name"),
+                e.errors[0].1
+            ),
+            _ => panic!("reserved word error expected"),
+        }
+
+        // 修飾子や型名も識別子の区切りで判定する。
+        // 修飾子で始まる識別子は修飾子変数か型の別名
+        assert_eq!(
+            Ok((
+                "",
+                TypeExpr {
+                    qual: Qual::Var("linear".to_string()),
+                    prim: PrimType::Bool
+                }
+            )),
+            parse_type("linear bool")
+        );
+        assert_eq!(
+            Ok((
+                " = un true",
+                TypeExpr {
+                    qual: Qual::Un,
+                    prim: PrimType::Alias("linear".to_string())
+                }
+            )),
+            parse_type("linear = un true")
+        );
+        assert!(parse_type("lin boolean").is_err());
+        assert!(parse_type("lin bool").is_ok());
+    }
 }
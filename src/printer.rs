@@ -0,0 +1,425 @@
+//! 抽象構文木をLinzinのソースコードとして出力するプリティプリンタ
+//!
+//! 波括弧の中は4文字分インデントし、`let`や`;`で区切られた式は1行ずつ出力する。
+//! 出力したソースコードをパースすると、位置情報を除いて元の抽象構文木と一致する。
+
+use crate::parser::{self, Comment, Expr, Qual, TypeExpr, ValExpr};
+
+const INDENT: &str = "    ";
+
+/// 式をソースコードに変換
+pub fn print(expr: &Expr) -> String {
+    print_with_comments(expr, "", &[])
+}
+
+/// 式を、パース元のソースコードsrcにあったコメントを含めてソースコードに変換
+///
+/// コメントは、ソースコード上でその後に続くトークンの直前に出力する。
+/// 後ろの同じ行にトークンが続くブロックコメントは、改行せずにそのトークンの直前に出力する。
+/// それ以外のコメントは、直前のトークンと同じ行にあった場合はその行末に、
+/// そうでなければ単独の行に出力する。
+pub fn print_with_comments(expr: &Expr, src: &str, comments: &[Comment]) -> String {
+    let mut p = Printer {
+        out: String::new(),
+        indent: 0,
+        src,
+        comments,
+        pos: 0,
+        last_end: None,
+    };
+    p.expr(expr);
+    p.flush_comments(usize::MAX);
+    p.out.truncate(p.out.trim_end().len());
+    p.out
+}
+
+#[derive(Debug)]
+struct Printer<'a> {
+    out: String,
+    indent: usize,           // 現在のインデントの深さ
+    src: &'a str,            // パース元のソースコード
+    comments: &'a [Comment], // 未出力のコメント
+    pos: usize,              // 次に出力するトークンを探し始める、ソースコード上の位置
+    last_end: Option<usize>, // 最後に出力した式かトークンのソースコード上の終了位置
+}
+
+impl Printer<'_> {
+    // 改行し、インデントを出力。空行の場合はインデントのみやり直す
+    fn newline(&mut self) {
+        let len = self.out.trim_end_matches(' ').len();
+        self.out.truncate(len);
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    // ソースコード上でposより前にあるコメントを出力
+    fn flush_comments(&mut self, pos: usize) {
+        while let Some((c, rest)) = self.comments.split_first() {
+            if c.span.start >= pos {
+                break;
+            }
+            self.comments = rest;
+
+            // 後ろの同じ行にトークンが続くブロックコメントは、そのトークンの直前に出力
+            if is_inline(self.src, c) {
+                if !self.out.is_empty() && !self.out.ends_with([' ', '\n', '(', '<']) {
+                    self.out.push(' ');
+                }
+                self.out.push_str(&c.text);
+                self.out.push(' ');
+                continue;
+            }
+
+            // 直前の式と同じ行にあったコメントは行末に続けて出力
+            let trailing = matches!(
+                self.last_end,
+                Some(end) if end <= c.span.start && !self.src[end..c.span.start].contains('\n')
+            );
+            if trailing {
+                let len = self.out.trim_end().len();
+                self.out.truncate(len);
+                self.out.push(' ');
+            } else if !self.out.trim_end_matches(' ').ends_with('\n') && !self.out.trim().is_empty()
+            {
+                self.newline();
+            }
+            self.out.push_str(&c.text);
+            self.newline();
+        }
+    }
+
+    // sを字句に分けて出力。空白以外の字句はtokenで出力する
+    fn tokens(&mut self, s: &str) {
+        let mut rest = s;
+        while let Some(c) = rest.chars().next() {
+            let len = if is_ident_char(c) {
+                rest.find(|c| !is_ident_char(c)).unwrap_or(rest.len())
+            } else {
+                c.len_utf8()
+            };
+            let (t, r) = rest.split_at(len);
+            if c.is_whitespace() {
+                self.out.push_str(t);
+            } else {
+                self.token(t);
+            }
+            rest = r;
+        }
+    }
+
+    // トークンtを出力。ソースコード上の次のトークンがtであれば、その前にあるコメントを先に出力する
+    //
+    // `()`を`un ()`とする場合など、ソースコードにないトークンはそのまま出力する
+    fn token(&mut self, t: &str) {
+        let rest = self.src.get(self.pos..).unwrap_or("");
+        let start = match parser::sp0(rest) {
+            Ok((r, _)) => self.src.len() - r.len(),
+            Err(_) => self.pos,
+        };
+        if let Some(r) = self.src.get(start..).and_then(|r| r.strip_prefix(t)) {
+            // 識別子の一部には一致しない
+            if !(t.ends_with(is_ident_char) && r.starts_with(is_ident_char)) {
+                self.flush_comments(start);
+                self.pos = start + t.len();
+                self.last_end = Some(self.pos);
+            }
+        }
+        self.out.push_str(t);
+    }
+
+    // { <E> }を、中身を一段深くインデントして出力。endは閉じ括弧のソースコード上の位置
+    fn block(&mut self, expr: &Expr, end: usize) {
+        self.tokens("{");
+        self.indent += 1;
+        self.newline();
+        self.expr(expr);
+        self.flush_comments(end);
+        self.indent -= 1;
+        self.newline();
+        self.tokens("}");
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        self.flush_comments(expr.span().start);
+        self.pos = self.pos.max(expr.span().start);
+        match expr {
+            Expr::Let(e) => {
+                let rec = if e.rec { "rec " } else { "" };
+                self.tokens(&format!("let {rec}{}{} = ", e.var, annotation(&e.ty)));
+                self.expr(&e.expr1);
+                self.tokens(";");
+                self.newline();
+                self.expr(&e.expr2);
+            }
+            Expr::If(e) => {
+                self.tokens("if ");
+                self.expr(&e.cond_expr);
+                self.tokens(" ");
+                self.block(&e.then_expr, e.else_expr.span().start);
+                self.tokens(" else ");
+                self.block(&e.else_expr, e.span.end);
+            }
+            Expr::Split(e) => {
+                self.tokens("split ");
+                self.expr(&e.expr);
+                self.tokens(&format!(" as {}, {} ", e.left, e.right));
+                self.block(&e.body, e.span.end);
+            }
+            Expr::Case(e) => {
+                self.tokens("case ");
+                self.expr(&e.expr);
+                self.tokens(&format!(" of inl {} ", e.inl_var));
+                self.block(&e.inl_expr, e.inr_expr.span().start);
+                self.tokens(&format!(" | inr {} ", e.inr_var));
+                self.block(&e.inr_expr, e.span.end);
+            }
+            Expr::Free(e) => {
+                self.tokens(&format!("free {}", e.var));
+                // 続く式がun ()の場合は省略
+                if !is_unit(&e.expr) {
+                    self.tokens(";");
+                    self.newline();
+                    self.expr(&e.expr);
+                }
+            }
+            Expr::App(e) => {
+                // 入れ子の関数適用は(f a b)のようにまとめて出力
+                let mut args = vec![&e.expr2];
+                let mut f = &e.expr1;
+                while let Expr::App(app) = f.as_ref() {
+                    args.push(&app.expr2);
+                    f = &app.expr1;
+                }
+                self.tokens("(");
+                self.expr(f);
+                for arg in args.into_iter().rev() {
+                    self.tokens(" ");
+                    self.expr(arg);
+                }
+                self.tokens(")");
+            }
+            Expr::BinOp(e) => {
+                self.tokens("(");
+                self.expr(&e.expr1);
+                self.tokens(&format!(" {} ", e.op));
+                self.expr(&e.expr2);
+                self.tokens(")");
+            }
+            Expr::Var(e) => self.tokens(&e.var),
+            Expr::QVal(e) => {
+                self.tokens(&format!("{} ", e.qual));
+                self.val(&e.val, e.span.end);
+            }
+            Expr::Def(e) => {
+                self.tokens(&format!("def {}{} = ", e.var, annotation(&e.ty)));
+                self.expr(&e.expr);
+                self.tokens(";");
+            }
+            Expr::Type(e) => {
+                self.tokens(&format!("type {} = {};", e.name, e.ty));
+                self.newline();
+                self.expr(&e.expr);
+            }
+            Expr::Env(e) => {
+                self.tokens("env;");
+                self.newline();
+                self.expr(&e.expr);
+            }
+            Expr::Seq(e) => {
+                self.expr(&e.expr1);
+                self.tokens(";");
+                self.newline();
+                self.expr(&e.expr2);
+            }
+        }
+        self.last_end = Some(expr.span().end);
+    }
+
+    // endは値のソースコード上の終了位置
+    fn val(&mut self, val: &ValExpr, end: usize) {
+        match val {
+            ValExpr::Bool(v) => self.tokens(&v.to_string()),
+            ValExpr::Int(n) => self.tokens(&n.to_string()),
+            ValExpr::Unit => self.tokens("()"),
+            ValExpr::Pair(e1, e2) => {
+                self.tokens("<");
+                self.expr(e1);
+                self.tokens(", ");
+                self.expr(e2);
+                self.tokens(">");
+            }
+            ValExpr::Inl(e) | ValExpr::Inr(e) => {
+                let side = if let ValExpr::Inl(_) = val {
+                    "inl"
+                } else {
+                    "inr"
+                };
+                self.tokens(&format!("{side} ({} + {}) ", e.left_ty, e.right_ty));
+                self.expr(&e.expr);
+            }
+            ValExpr::Fun(e) => {
+                self.tokens(&format!("fn {} : {}", e.var, e.ty));
+                for p in e.rest.iter() {
+                    self.tokens(&format!(", {} : {}", p.var, p.ty));
+                }
+                self.tokens(" ");
+                self.block(&e.expr, end);
+            }
+        }
+    }
+}
+
+/// 型注釈。省略されている場合は空文字列
+fn annotation(ty: &Option<TypeExpr>) -> String {
+    match ty {
+        Some(ty) => format!(" : {ty}"),
+        None => String::new(),
+    }
+}
+
+/// 識別子や予約語、整数を構成する文字か
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '\''
+}
+
+/// 後ろの同じ行にトークンが続くブロックコメントか
+fn is_inline(src: &str, c: &Comment) -> bool {
+    c.text.starts_with("/*")
+        && src
+            .get(c.span.end..)
+            .and_then(|r| r.lines().next())
+            .is_some_and(|l| !l.trim().is_empty())
+}
+
+/// un ()かどうか
+fn is_unit(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::QVal(e) if e.qual == Qual::Un && e.val == ValExpr::Unit
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parser, printer::*};
+    use std::{fs, path::Path};
+
+    // 位置情報を除いてパース
+    fn parse_without_span(input: &str) -> Option<Expr> {
+        let (_, mut expr) = parser::parse(input).ok()?;
+        expr.map_span(&|_| parser::Span::default());
+        Some(expr)
+    }
+
+    #[test]
+    fn test_print() {
+        let input = "let x : lin bool = lin true; split lin <x, un fn y : un int { (y + un 1) }> as a, b { free a; (b un -2) }";
+        let expr = parse_without_span(input).unwrap();
+        assert_eq!(
+            "let x : lin bool = lin true;
+split lin <x, un fn y : un int {
+    (y + un 1)
+}> as a, b {
+    free a;
+    (b un -2)
+}",
+            print(&expr)
+        );
+        assert_eq!(
+            "case un inl (un bool + un int) un () of inl a {
+    a
+} | inr b {
+    (b < un 0)
+}",
+            print(
+                &parse_without_span(
+                    "case un inl (un bool + un int) () of inl a { a } | inr b { (b < un 0) }"
+                )
+                .unwrap()
+            )
+        );
+        assert_eq!(
+            "let x = un 1;\ndef y = x;",
+            print(&parse_without_span("let x=un 1; def y =x;").unwrap())
+        );
+        assert_eq!(
+            "(f un 1 un 2 (g un 3))",
+            print(&parse_without_span("((f un 1) un 2 (g un 3))").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_print_with_comments() {
+        let input = "// 先頭のコメント
+let x : lin bool = lin true; // 行末のコメント
+split lin <x, un false> as a, b {
+  // 本体の前のコメント
+  free b; a // 本体の後のコメント
+}
+// 末尾のコメント
+";
+        let formatted = crate::format(input).unwrap();
+        assert_eq!(
+            "// 先頭のコメント
+let x : lin bool = lin true; // 行末のコメント
+split lin <x, un false> as a, b {
+    // 本体の前のコメント
+    free b;
+    a // 本体の後のコメント
+}
+// 末尾のコメント
+",
+            formatted
+        );
+        // 整形済みのソースコードは変わらない
+        assert_eq!(formatted, crate::format(&formatted).unwrap());
+    }
+
+    #[test]
+    fn test_print_inline_comments() {
+        // 後ろにトークンが続くブロックコメントは、そのトークンの直前に残す
+        let input = "let x /* c1 */ : lin bool /* c2 */ = lin true;
+(f /* arg */ un 1)
+";
+        assert_eq!(input, crate::format(input).unwrap());
+        assert_eq!(
+            "let f : un (/* 引数 */ un int -> un int) = g;\nf\n",
+            crate::format("let f : un (/* 引数 */ un int -> un int) = g; f").unwrap()
+        );
+        assert_eq!(
+            "split lin <un 1, /* snd */ un 2> as a, b {\n    /* body */ a\n}\n",
+            crate::format("split lin <un 1,/* snd */un 2> as a, b { /* body */ a }").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // codes/以下のパースできるファイルは、出力してパースし直しても同じ抽象構文木になる
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("codes");
+        let mut n = 0;
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|e| e != "lin") {
+                continue;
+            }
+            let content = fs::read_to_string(&path).unwrap();
+            let expr = match parse_without_span(&content) {
+                Some(expr) => expr,
+                None => continue,
+            };
+            let printed = print(&expr);
+            assert_eq!(
+                Some(&expr),
+                parse_without_span(&printed).as_ref(),
+                "{}:\n{printed}",
+                path.display()
+            );
+            n += 1;
+        }
+        assert!(n > 0);
+    }
+}
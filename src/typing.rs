@@ -1,10 +1,14 @@
-use crate::{helper::safe_add, parser};
-use std::{borrow::Cow, cmp::Ordering, collections::BTreeMap, mem};
+use crate::{
+    error::{Branch, Error, ErrorKind, Scope},
+    helper::safe_add,
+    parser,
+};
+use std::{cmp::Ordering, collections::BTreeMap, mem};
 
 type VarToType = BTreeMap<String, Option<parser::TypeExpr>>;
 
 /// 型環境
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
 pub struct TypeEnv {
     pub env_lin: TypeEnvStack, // lin用
     pub env_un: TypeEnvStack,  // un用
@@ -39,16 +43,25 @@ impl TypeEnv {
     }
 
     /// 型環境へ変数と型をpush
+    ///
+    /// 修飾子変数の型の変数は、linにもなりうるためlin用の型環境に入れる
     pub(crate) fn insert(&mut self, key: String, value: parser::TypeExpr) {
-        if value.qual == parser::Qual::Lin {
-            self.env_lin.insert(key, value);
-        } else if value.qual == parser::Qual::Un {
-            self.env_un.insert(key, value);
-        } else {
-            self.env_aff.insert(key, value);
+        match value.qual {
+            parser::Qual::Lin | parser::Qual::Var(_) => self.env_lin.insert(key, value),
+            parser::Qual::Un => self.env_un.insert(key, value),
+            parser::Qual::Aff => self.env_aff.insert(key, value),
         }
     }
 
+    /// 変数の型を取得。未定義か消費済みの場合はNone
+    pub fn get(&self, key: &str) -> Option<&parser::TypeExpr> {
+        [&self.env_lin, &self.env_un, &self.env_aff]
+            .into_iter()
+            .filter_map(|stack| stack.get(key))
+            .max_by_key(|(depth, _)| *depth)
+            .and_then(|(_, t)| t.as_ref())
+    }
+
     /// linとunの型環境からget_mutし、depthが大きい方を返す
     pub(crate) fn get_mut(&mut self, key: &str) -> Option<&mut Option<parser::TypeExpr>> {
         if let Some((d1, t1)) = self.env_lin.get_mut(key) {
@@ -134,6 +147,16 @@ impl TypeEnvStack {
     }
 
     // スタックを上からたどっていき、はじめに見つかる変数の型を取得
+    fn get(&self, key: &str) -> Option<(usize, &Option<parser::TypeExpr>)> {
+        for (depth, elm) in self.vars.iter().rev() {
+            if let Some(e) = elm.get(key) {
+                return Some((*depth, e));
+            }
+        }
+        None
+    }
+
+    // getの可変版
     pub(crate) fn get_mut(&mut self, key: &str) -> Option<(usize, &mut Option<parser::TypeExpr>)> {
         for (depth, elm) in self.vars.iter_mut().rev() {
             if let Some(e) = elm.get_mut(key) {
@@ -144,58 +167,107 @@ impl TypeEnvStack {
     }
 }
 
-type TResult<'a> = Result<parser::TypeExpr, Cow<'a, str>>;
+type TResult = Result<parser::TypeExpr, Error>;
 
 /// 型付け関数
 /// 式を受け取り、型を返す
-pub fn typing<'a>(expr: &parser::Expr, env: &mut TypeEnv, depth: usize) -> TResult<'a> {
-    match expr {
+///
+/// エラーに位置情報がない場合は、この式の位置を設定する
+pub fn typing(expr: &mut parser::Expr, env: &mut TypeEnv, depth: usize) -> TResult {
+    let ret = match &mut *expr {
         parser::Expr::App(e) => typing_app(e, env, depth),
+        parser::Expr::BinOp(e) => typing_binop(e, env, depth),
         parser::Expr::QVal(e) => typing_qval(e, env, depth),
         parser::Expr::Free(e) => typing_free(e, env, depth),
         parser::Expr::If(e) => typing_if(e, env, depth),
         parser::Expr::Split(e) => typing_split(e, env, depth),
-        parser::Expr::Var(e) => typing_var(e, env),
+        parser::Expr::Case(e) => typing_case(e, env, depth),
+        parser::Expr::Var(e) => typing_var(&e.var, env),
         parser::Expr::Let(e) => typing_let(e, env, depth),
         parser::Expr::Def(e) => typing_def(e, env, depth),
         parser::Expr::Env(e) => typing_env(e, env, depth),
-    }
+        parser::Expr::Type(e) => typing(&mut e.expr, env, depth), // 別名は解決済み
+        parser::Expr::Seq(e) => typing_seq(e, env, depth),
+    };
+    ret.map_err(|e| e.or_span(expr.span()))
 }
 
 /// 関数適用の型付け
-fn typing_app<'a>(expr: &parser::AppExpr, env: &mut TypeEnv, depth: usize) -> TResult<'a> {
+fn typing_app(expr: &mut parser::AppExpr, env: &mut TypeEnv, depth: usize) -> TResult {
     // 関数部分
-    let t1 = typing(&expr.expr1, env, depth)?;
-    let t_arg;
-    let t_ret;
-    match t1.prim {
-        parser::PrimType::Arrow(a, b) => {
-            t_arg = a; // 引数の型
-            t_ret = b; // 返り値の型
-        }
-        _ => return Err("not a function type".into()),
-    }
+    let t1 = typing(&mut expr.expr1, env, depth)?;
+    // 量化した修飾子変数と型変数、引数と返り値の型
+    let (params, t_arg, t_ret) = match &t1.prim {
+        parser::PrimType::Arrow(a, b) => (&[][..], a, b),
+        parser::PrimType::Forall(params, prim) => match &**prim {
+            parser::PrimType::Arrow(a, b) => (&params[..], a, b),
+            _ => return Err(ErrorKind::NotAFunction(t1.clone()).into()),
+        },
+        _ => return Err(ErrorKind::NotAFunction(t1.clone()).into()),
+    };
 
     // 引数部分
-    let t2 = typing(&expr.expr2, env, depth)?;
+    let t2 = typing(&mut expr.expr2, env, depth)?;
+
+    // 修飾子変数と型変数を引数の型に合わせて具体化
+    let subst = infer_params(params, t_arg, &t2);
+    check_bounds(params, &subst).map_err(|e| e.or_span(expr.expr2.span()))?;
+    let t_arg = subst_type(t_arg, &subst);
+    let t_ret = generalize(params, &subst, subst_type(t_ret, &subst));
 
-    // 引数の型が一致しているかチェック
-    if *t_arg == t2 {
-        Ok(*t_ret)
+    // 引数の型が関数の引数の型の部分型かチェック
+    if is_subtype(&t2, &t_arg) {
+        Ok(t_ret)
     } else {
-        Err("different argument type when applying functions".into())
+        Err(Error::new(
+            ErrorKind::ArgumentMismatch {
+                expected: t_arg,
+                found: t2,
+            },
+            expr.expr2.span(),
+        ))
+    }
+}
+
+/// 二項演算の型付け
+///
+/// 両辺はint型で、結果の修飾子は両辺の修飾子のうち制約の強い方（un < aff < lin）となる
+fn typing_binop(expr: &mut parser::BinOpExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    let t1 = typing(&mut expr.expr1, env, depth)?;
+    let t2 = typing(&mut expr.expr2, env, depth)?;
+
+    for (t, e) in [(&t1, &expr.expr1), (&t2, &expr.expr2)] {
+        if t.prim != parser::PrimType::Int {
+            return Err(Error::new(
+                ErrorKind::NotAnInt {
+                    op: expr.op,
+                    found: t.clone(),
+                },
+                e.span(),
+            ));
+        }
     }
+
+    let prim = if expr.op.is_comparison() {
+        parser::PrimType::Bool
+    } else {
+        parser::PrimType::Int
+    };
+
+    Ok(parser::TypeExpr {
+        qual: t1.qual.join(&t2.qual),
+        prim,
+    })
 }
 
 /// 修飾子付き値の型付け
-pub(crate) fn typing_qval<'a>(
-    expr: &parser::QValExpr,
-    env: &mut TypeEnv,
-    depth: usize,
-) -> TResult<'a> {
+pub(crate) fn typing_qval(expr: &mut parser::QValExpr, env: &mut TypeEnv, depth: usize) -> TResult {
     // プリミティブ型を計算
-    let p = match &expr.val {
+    let inl = matches!(expr.val, parser::ValExpr::Inl(_));
+    let p = match &mut expr.val {
         parser::ValExpr::Bool(_) => parser::PrimType::Bool,
+        parser::ValExpr::Int(_) => parser::PrimType::Int,
+        parser::ValExpr::Unit => parser::PrimType::Unit,
         parser::ValExpr::Pair(e1, e2) => {
             // 式e1とe2をtypingにより型付け
             let t1 = typing(e1, env, depth)?;
@@ -204,22 +276,63 @@ pub(crate) fn typing_qval<'a>(
             // expr.qualがUnであり、
             // e1か、e2の型にlinが含まれていた場合、型付けエラー
             if expr.qual == parser::Qual::Un
-                && (t1.qual == parser::Qual::Lin
-                    || t2.qual == parser::Qual::Lin
-                    || t1.qual == parser::Qual::Aff
-                    || t2.qual == parser::Qual::Aff)
+                && (t1.qual != parser::Qual::Un || t2.qual != parser::Qual::Un)
             {
-                return Err("using lin or aff type within a pair of un types".into());
+                return Err(ErrorKind::LinearInUnPair.into());
             }
 
             // ペア型を返す
             parser::PrimType::Pair(Box::new(t1), Box::new(t2))
         }
+        parser::ValExpr::Inl(e) | parser::ValExpr::Inr(e) => {
+            let t = typing(&mut e.expr, env, depth)?;
+
+            // 値の型は注入する側の型と一致
+            let expected = if inl { &e.left_ty } else { &e.right_ty };
+            if !is_subtype(&t, expected) {
+                return Err(Error::new(
+                    ErrorKind::InjectionMismatch {
+                        expected: expected.clone(),
+                        found: t,
+                    },
+                    e.expr.span(),
+                ));
+            }
+
+            // expr.qualがUnであり、直和の型にlinかaffが含まれていた場合、型付けエラー
+            if expr.qual == parser::Qual::Un
+                && (e.left_ty.qual != parser::Qual::Un || e.right_ty.qual != parser::Qual::Un)
+            {
+                return Err(ErrorKind::LinearInUnSum.into());
+            }
+
+            parser::PrimType::Sum(Box::new(e.left_ty.clone()), Box::new(e.right_ty.clone()))
+        }
         parser::ValExpr::Fun(e) => {
             // 関数の型付け
 
-            // un型の関数内では、lin型の自由変数をキャプチャできないため
-            // lin用の型環境を置き換え
+            // 修飾子変数qの型の変数をキャプチャする関数は、q以上の修飾子でなければならない
+            for v in e.free_vars() {
+                if let Some(
+                    t @ parser::TypeExpr {
+                        qual: q @ parser::Qual::Var(_),
+                        ..
+                    },
+                ) = env.get(&v)
+                {
+                    if !q.is_sub(&expr.qual) {
+                        return Err(ErrorKind::CaptureQualifier {
+                            var: v,
+                            ty: t.clone(),
+                            closure: expr.qual.clone(),
+                        }
+                        .into());
+                    }
+                }
+            }
+
+            // un型の関数内では、lin, aff型の自由変数をキャプチャできないため
+            // lin, aff用の型環境を置き換え
             let env_prev_lin = if expr.qual == parser::Qual::Un {
                 Some(mem::take(&mut env.env_lin))
             } else {
@@ -233,18 +346,22 @@ pub(crate) fn typing_qval<'a>(
 
             // depthをインクリメントしてpush
             let mut depth = depth;
-            safe_add(&mut depth, &1, || "variable scope nesting is too deep")?;
+            safe_add(&mut depth, &1, || ErrorKind::NestingTooDeep)?;
             env.push(depth);
             env.insert(e.var.clone(), e.ty.clone());
 
             // 関数中の式を型付け
-            let t = typing(&e.expr, env, depth)?;
+            let t = typing(&mut e.expr, env, depth)?;
 
             // スタックをpopし、popした型環境の中にlin型が含まれていた場合、型付けエラー
             let (elin, _, _) = env.pop(depth);
             for (k, v) in elin.unwrap().iter() {
                 if v.is_some() {
-                    return Err(format!("the variable \"{k}\" of type lin is not consumed in the function definition.").into());
+                    return Err(ErrorKind::LinearNotConsumed {
+                        var: k.clone(),
+                        scope: Scope::Function,
+                    }
+                    .into());
                 }
             }
 
@@ -253,7 +370,7 @@ pub(crate) fn typing_qval<'a>(
                 env.env_lin = ep;
             }
             if let Some(ep) = env_prev_aff {
-                env.env_lin = ep;
+                env.env_aff = ep;
             }
 
             // 関数型を返す
@@ -263,76 +380,81 @@ pub(crate) fn typing_qval<'a>(
 
     // 修飾子付き型を返す
     Ok(parser::TypeExpr {
-        qual: expr.qual,
+        qual: expr.qual.clone(),
         prim: p,
     })
 }
 
 /// free式の型付け
-fn typing_free<'a>(expr: &parser::FreeExpr, env: &mut TypeEnv, depth: usize) -> TResult<'a> {
+fn typing_free(expr: &mut parser::FreeExpr, env: &mut TypeEnv, depth: usize) -> TResult {
     if let Some((_, t)) = env.env_lin.get_mut(&expr.var) {
         if t.is_some() {
             *t = None;
-            return typing(&expr.expr, env, depth);
+            return typing(&mut expr.expr, env, depth);
         }
     }
     if let Some((_, t)) = env.env_aff.get_mut(&expr.var) {
         if t.is_some() {
             *t = None;
-            return typing(&expr.expr, env, depth);
+            return typing(&mut expr.expr, env, depth);
         }
     }
-    Err(format!(
-        "The variable \"{}\" has already been freed or is not a lin or aff type.",
-        expr.var
-    )
-    .into())
+    Err(ErrorKind::CannotFree(expr.var.clone()).into())
 }
 
 /// if式の型付け
-fn typing_if<'a>(expr: &parser::IfExpr, env: &mut TypeEnv, depth: usize) -> TResult<'a> {
-    let t1 = typing(&expr.cond_expr, env, depth)?;
+fn typing_if(expr: &mut parser::IfExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    let t1 = typing(&mut expr.cond_expr, env, depth)?;
     // 条件の式の型はbool
     if t1.prim != parser::PrimType::Bool {
-        return Err("conditional expression in if-statement is not bool".into());
+        return Err(Error::new(ErrorKind::NotABool(t1), expr.cond_expr.span()));
     }
 
     let mut e = env.clone();
-    let t2 = typing(&expr.then_expr, &mut e, depth)?;
-    let t3 = typing(&expr.else_expr, env, depth)?;
+    let t2 = typing(&mut expr.then_expr, &mut e, depth)?;
+    let t3 = typing(&mut expr.else_expr, env, depth)?;
 
-    // thenとelse部の型は同じで、
+    // thenとelse部の型は共通の上界を持ち、
     // thenとelse部評価後の型環境は同じかをチェック
-    if t2 != t3 || e != *env {
-        return Err("the types of then and else expressions in if statement are different.".into());
+    let t = join_type(&t2, &t3).ok_or(ErrorKind::BranchTypeMismatch(Branch::If))?;
+    if e != *env {
+        return Err(ErrorKind::BranchEnvMismatch(Branch::If).into());
     }
 
-    Ok(t2)
+    Ok(t)
 }
 
 /// split式の型付け
-fn typing_split<'a>(expr: &parser::SplitExpr, env: &mut TypeEnv, depth: usize) -> TResult<'a> {
+fn typing_split(expr: &mut parser::SplitExpr, env: &mut TypeEnv, depth: usize) -> TResult {
     if expr.left == expr.right {
-        return Err("splitの変数名が同じ".into());
+        return Err(ErrorKind::DuplicateVariable(expr.left.clone()).into());
     }
 
-    let t1 = typing(&expr.expr, env, depth)?;
+    let t1 = typing(&mut expr.expr, env, depth)?;
     let mut depth = depth;
-    safe_add(&mut depth, &1, || "variable scope nesting is too deep")?;
+    safe_add(&mut depth, &1, || ErrorKind::NestingTooDeep)?;
 
     match t1.prim {
         parser::PrimType::Pair(p1, p2) => {
+            // 評価で用いるため変数の型を記録
+            expr.tys = Some((*p1.clone(), *p2.clone()));
             env.push(depth);
             // ローカル変数の型を追加
             env.insert(expr.left.clone(), *p1);
             env.insert(expr.right.clone(), *p2);
         }
-        _ => {
-            return Err("argument of split is not a pair type".into());
+        t => {
+            return Err(Error::new(
+                ErrorKind::NotAPair(parser::TypeExpr {
+                    qual: t1.qual,
+                    prim: t,
+                }),
+                expr.expr.span(),
+            ));
         }
     }
 
-    let ret = typing(&expr.body, env, depth);
+    let ret = typing(&mut expr.body, env, depth);
 
     // ローカル変数を削除
     let (elin, _, _) = env.pop(depth);
@@ -340,9 +462,72 @@ fn typing_split<'a>(expr: &parser::SplitExpr, env: &mut TypeEnv, depth: usize) -
     // lin型の変数を消費しているかチェック
     for (k, v) in elin.unwrap().iter() {
         if v.is_some() {
-            return Err(format!(
-                "the variable \"{k}\" of type lin is not consumed in the expression of split."
-            )
+            return Err(ErrorKind::LinearNotConsumed {
+                var: k.clone(),
+                scope: Scope::Split,
+            }
+            .into());
+        }
+    }
+
+    ret
+}
+
+/// case式の型付け
+fn typing_case(expr: &mut parser::CaseExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    let t1 = typing(&mut expr.expr, env, depth)?;
+    let mut depth = depth;
+    safe_add(&mut depth, &1, || ErrorKind::NestingTooDeep)?;
+
+    let (tl, tr) = match t1.prim {
+        parser::PrimType::Sum(tl, tr) => (*tl, *tr),
+        t => {
+            return Err(Error::new(
+                ErrorKind::NotASum(parser::TypeExpr {
+                    qual: t1.qual,
+                    prim: t,
+                }),
+                expr.expr.span(),
+            ))
+        }
+    };
+
+    // 評価で用いるため変数の型を記録
+    expr.tys = Some((tl.clone(), tr.clone()));
+
+    // inlとinr部の型は共通の上界を持ち、
+    // inlとinr部評価後の型環境は同じかをチェック
+    let mut e = env.clone();
+    let t2 = typing_case_branch(&expr.inl_var, tl, &mut expr.inl_expr, &mut e, depth)?;
+    let t3 = typing_case_branch(&expr.inr_var, tr, &mut expr.inr_expr, env, depth)?;
+    let t = join_type(&t2, &t3).ok_or(ErrorKind::BranchTypeMismatch(Branch::Case))?;
+    if e != *env {
+        return Err(ErrorKind::BranchEnvMismatch(Branch::Case).into());
+    }
+
+    Ok(t)
+}
+
+/// case式の各分岐の型付け
+fn typing_case_branch(
+    var: &str,
+    ty: parser::TypeExpr,
+    body: &mut parser::Expr,
+    env: &mut TypeEnv,
+    depth: usize,
+) -> TResult {
+    env.push(depth);
+    env.insert(var.to_string(), ty);
+    let ret = typing(body, env, depth);
+
+    // ローカル変数を削除し、lin型の変数を消費しているかチェック
+    let (elin, _, _) = env.pop(depth);
+    for (k, v) in elin.unwrap().iter() {
+        if v.is_some() {
+            return Err(ErrorKind::LinearNotConsumed {
+                var: k.clone(),
+                scope: Scope::Case,
+            }
             .into());
         }
     }
@@ -351,14 +536,14 @@ fn typing_split<'a>(expr: &parser::SplitExpr, env: &mut TypeEnv, depth: usize) -
 }
 
 /// 変数の型付け
-fn typing_var<'a>(expr: &str, env: &mut TypeEnv) -> TResult<'a> {
+fn typing_var(expr: &str, env: &mut TypeEnv) -> TResult {
     let ret = env.get_mut(expr);
     if let Some(it) = ret {
         // 定義されている
         if let Some(t) = it {
             // 消費されていない
-            if t.qual == parser::Qual::Lin || t.qual == parser::Qual::Aff {
-                // lin or aff型
+            if t.qual != parser::Qual::Un {
+                // lin, aff型か修飾子変数の型
                 let eret = t.clone();
                 *it = None; // lin or affを消費
                 return Ok(eret);
@@ -367,37 +552,50 @@ fn typing_var<'a>(expr: &str, env: &mut TypeEnv) -> TResult<'a> {
                 return Ok(t);
             }
         }
+        return Err(ErrorKind::AlreadyUsed(expr.to_string()).into());
     }
 
-    Err(format!(
-        "The variable \"{}\" is either not defined, already used, or cannot be captured.",
-        expr
-    )
-    .into())
+    Err(ErrorKind::UnboundVariable(expr.to_string()).into())
 }
 
 /// let式の型付け
-fn typing_let<'a>(expr: &parser::LetExpr, env: &mut TypeEnv, depth: usize) -> TResult<'a> {
+fn typing_let(expr: &mut parser::LetExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    let mut inner = depth;
+    safe_add(&mut inner, &1, || ErrorKind::NestingTooDeep)?;
+
     // 変数束縛
-    let t1 = typing(&expr.expr1, env, depth)?;
+    let t1 = if expr.rec {
+        // 再帰関数は本体から自身を参照できるよう、先に変数の型をinsert
+        let ty = check_rec(expr)?.clone();
+        env.push(inner);
+        env.insert(expr.var.clone(), ty);
+        typing(&mut expr.expr1, env, inner)?
+    } else {
+        typing(&mut expr.expr1, env, depth)?
+    };
     // 束縛変数の型をチェック
-    if t1 != expr.ty {
-        return Err(format!("The type of the variable \"{}\" is different.", expr.var).into());
+    let t1 = check_annotation(&expr.var, &expr.ty, t1, expr.expr1.span())?;
+    // 型注釈がない場合は、評価で用いるため求めた型を記録
+    if expr.ty.is_none() {
+        expr.inferred = Some(t1.clone());
     }
     // 関数内
-    let mut depth = depth;
-    safe_add(&mut depth, &1, || "variable scope nesting is too deep")?;
-    env.push(depth);
-    env.insert(expr.var.clone(), t1); // 変数の型をinsert
-    let t2 = typing(&expr.expr2, env, depth)?;
+    let depth = inner;
+    if !expr.rec {
+        env.push(depth);
+        env.insert(expr.var.clone(), t1); // 変数の型をinsert
+    }
+    let t2 = typing(&mut expr.expr2, env, depth)?;
 
     // lin型の変数を消費しているかチェック
     let (elin, _eun, _eaff) = env.pop(depth);
     for (k, v) in elin.unwrap().iter() {
         if v.is_some() {
-            return Err(
-                format!("lin type variable \"{k}\" does not consumed in let expression").into(),
-            );
+            return Err(ErrorKind::LinearNotConsumed {
+                var: k.clone(),
+                scope: Scope::Let,
+            }
+            .into());
         }
     }
     // un, affはglobalに保存
@@ -421,22 +619,895 @@ fn typing_let<'a>(expr: &parser::LetExpr, env: &mut TypeEnv, depth: usize) -> TR
     Ok(t2)
 }
 
+/// 再帰関数の束縛をチェック
+///
+/// 再帰関数は本体から何度でも呼び出されうるため、un型の関数のみ許す。
+/// 型注釈が必要で、その型を返す
+fn check_rec(expr: &parser::LetExpr) -> Result<&parser::TypeExpr, Error> {
+    match &*expr.expr1 {
+        parser::Expr::QVal(parser::QValExpr {
+            val: parser::ValExpr::Fun(_),
+            ..
+        }) => (),
+        e => {
+            return Err(Error::new(
+                ErrorKind::RecNotFunction(expr.var.clone()),
+                e.span(),
+            ))
+        }
+    }
+    match &expr.ty {
+        Some(
+            ty @ parser::TypeExpr {
+                qual: parser::Qual::Un,
+                prim: parser::PrimType::Arrow(_, _),
+            },
+        ) => Ok(ty),
+        // 修飾子変数を量化した再帰関数
+        Some(
+            ty @ parser::TypeExpr {
+                qual: parser::Qual::Un,
+                prim: parser::PrimType::Forall(_, prim),
+            },
+        ) if matches!(**prim, parser::PrimType::Arrow(_, _)) => Ok(ty),
+        None => Err(Error::new(
+            ErrorKind::RecNotAnnotated(expr.var.clone()),
+            expr.span,
+        )),
+        _ => Err(Error::new(
+            ErrorKind::RecNotUn(expr.var.clone()),
+            expr.expr1.span(),
+        )),
+    }
+}
+
 /// defの型付け
-fn typing_def<'a>(expr: &parser::DefExpr, env: &mut TypeEnv, depth: usize) -> TResult<'a> {
+fn typing_def(expr: &mut parser::DefExpr, env: &mut TypeEnv, depth: usize) -> TResult {
     // 変数束縛
-    let t1 = typing(&expr.expr, env, depth)?;
+    let t1 = typing(&mut expr.expr, env, depth)?;
     // 束縛変数の型をチェック
-    if t1 != expr.ty {
-        return Err(format!("the type of the variable \"{}\" is different.", expr.var).into());
-    }
+    let t1 = check_annotation(&expr.var, &expr.ty, t1, expr.expr.span())?;
     env.insert(expr.var.clone(), t1.clone()); // 変数の型をinsert
 
     Ok(t1)
 }
 
+/// 束縛する値の型tが型注釈tyの部分型かチェックし、変数の型を返す
+///
+/// 型注釈がある場合は型注釈の型を、ない場合はtをそのまま変数の型とする
+fn check_annotation(
+    var: &str,
+    ty: &Option<parser::TypeExpr>,
+    t: parser::TypeExpr,
+    span: parser::Span,
+) -> TResult {
+    match ty {
+        Some(ty) if !is_subtype(&t, ty) => Err(Error::new(
+            ErrorKind::TypeMismatch {
+                var: var.to_string(),
+                expected: ty.clone(),
+                found: t,
+            },
+            span,
+        )),
+        Some(ty) => Ok(ty.clone()),
+        None => Ok(t),
+    }
+}
+
+/// t1がt2の部分型かどうか
+///
+/// 修飾子はun ≤ aff ≤ linの順で、制約の弱い型の値は制約の強い型の値として使える。
+/// ペア型と直和型は要素について共変、関数型は引数について反変、返り値について共変。
+///
+/// 修飾子変数qはun ≤ q ≤ linで、他の修飾子とは比較できない。型変数は同じ型変数とのみ等しい。
+/// t2が量化した型の場合は、任意の修飾子や型に対してt1がt2の部分型でなければならず、
+/// t1が量化した型の場合は、t2に合わせて具体化したt1がt2の部分型であればよい。
+///
+/// ```text
+/// un (lin bool -> un bool) ≤ lin (un bool -> lin bool)
+/// forall q. un (q bool -> q bool) ≤ un (lin bool -> lin bool)
+/// ```
+pub fn is_subtype(t1: &parser::TypeExpr, t2: &parser::TypeExpr) -> bool {
+    use parser::PrimType::*;
+    match (&t1.prim, &t2.prim) {
+        (_, Forall(_, p2)) => {
+            let t2 = parser::TypeExpr {
+                qual: t2.qual.clone(),
+                prim: (**p2).clone(),
+            };
+            return is_subtype(t1, &t2);
+        }
+        (Forall(params, p1), _) => {
+            let t1 = parser::TypeExpr {
+                qual: t1.qual.clone(),
+                prim: (**p1).clone(),
+            };
+            let subst = infer_params(params, &t1, t2);
+            return check_bounds(params, &subst).is_ok()
+                && is_subtype(&subst_type(&t1, &subst), t2);
+        }
+        _ => (),
+    }
+    if !t1.qual.is_sub(&t2.qual) {
+        return false;
+    }
+    match (&t1.prim, &t2.prim) {
+        (Pair(a1, b1), Pair(a2, b2)) | (Sum(a1, b1), Sum(a2, b2)) => {
+            is_subtype(a1, a2) && is_subtype(b1, b2)
+        }
+        (Arrow(a1, r1), Arrow(a2, r2)) => is_subtype(a2, a1) && is_subtype(r1, r2),
+        (p1, p2) => p1 == p2,
+    }
+}
+
+/// 量化した変数の具体化
+#[derive(Debug, Default, Clone)]
+struct Subst {
+    quals: BTreeMap<String, parser::Qual>, // 修飾子変数とその修飾子
+    types: BTreeMap<String, parser::TypeExpr>, // 型変数とその型
+}
+
+/// 量化した変数paramsを、paramsを含む型patternと具体的な型actualを照合して具体化
+///
+/// 複数箇所に現れる修飾子変数は、対応する修飾子のうち最も制約の強いものとし、
+/// 複数箇所に現れる型変数は、対応する型の最小の上界とする。
+/// actualに対応する箇所のない修飾子変数はunとし、型変数は具体化しない
+fn infer_params(
+    params: &[parser::TypeParam],
+    pattern: &parser::TypeExpr,
+    actual: &parser::TypeExpr,
+) -> Subst {
+    let mut found = Subst::default();
+    collect_params(pattern, actual, &mut found);
+
+    let mut subst = Subst::default();
+    for p in params {
+        if p.bound.is_some() {
+            if let Some(t) = found.types.remove(&p.name) {
+                subst.types.insert(p.name.clone(), t);
+            }
+        } else {
+            let q = found.quals.remove(&p.name).unwrap_or(parser::Qual::Un);
+            subst.quals.insert(p.name.clone(), q);
+        }
+    }
+    subst
+}
+
+fn collect_params(pattern: &parser::TypeExpr, actual: &parser::TypeExpr, found: &mut Subst) {
+    use parser::PrimType::*;
+    if let Var(v) = &pattern.prim {
+        // 型変数の修飾子は上限のため、修飾子変数としては扱わない
+        let t = match found.types.get(v) {
+            Some(t) => join_type(t, actual).unwrap_or_else(|| t.clone()),
+            None => actual.clone(),
+        };
+        found.types.insert(v.clone(), t);
+        return;
+    }
+    if let parser::Qual::Var(v) = &pattern.qual {
+        let q = match found.quals.get(v) {
+            Some(q) => q.join(&actual.qual),
+            None => actual.qual.clone(),
+        };
+        found.quals.insert(v.clone(), q);
+    }
+    match (&pattern.prim, &actual.prim) {
+        (Pair(a1, b1), Pair(a2, b2))
+        | (Sum(a1, b1), Sum(a2, b2))
+        | (Arrow(a1, b1), Arrow(a2, b2)) => {
+            collect_params(a1, a2, found);
+            collect_params(b1, b2, found);
+        }
+        _ => (),
+    }
+}
+
+/// 具体化した型変数の型の修飾子が、型変数の修飾子の上限以下かチェック
+fn check_bounds(params: &[parser::TypeParam], subst: &Subst) -> Result<(), Error> {
+    for p in params {
+        if let (Some(bound), Some(t)) = (&p.bound, subst.types.get(&p.name)) {
+            if !t.qual.is_sub(bound) {
+                return Err(ErrorKind::BoundViolation {
+                    var: p.name.clone(),
+                    bound: bound.clone(),
+                    found: t.clone(),
+                }
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 型中の修飾子変数と型変数を置き換える
+fn subst_type(t: &parser::TypeExpr, subst: &Subst) -> parser::TypeExpr {
+    use parser::PrimType::*;
+    if let Var(v) = &t.prim {
+        if let Some(t) = subst.types.get(v) {
+            return t.clone();
+        }
+    }
+    let qual = match &t.qual {
+        parser::Qual::Var(v) => subst
+            .quals
+            .get(v)
+            .cloned()
+            .unwrap_or_else(|| t.qual.clone()),
+        q => q.clone(),
+    };
+    let sub = |t: &parser::TypeExpr| Box::new(subst_type(t, subst));
+    let prim = match &t.prim {
+        Pair(t1, t2) => Pair(sub(t1), sub(t2)),
+        Sum(t1, t2) => Sum(sub(t1), sub(t2)),
+        Arrow(t1, t2) => Arrow(sub(t1), sub(t2)),
+        Forall(params, prim) => {
+            // 内側で量化した変数は置き換えない
+            let mut inner = subst.clone();
+            for p in params {
+                inner.quals.remove(&p.name);
+                inner.types.remove(&p.name);
+            }
+            let t = subst_type(
+                &parser::TypeExpr {
+                    qual: parser::Qual::Un,
+                    prim: (**prim).clone(),
+                },
+                &inner,
+            );
+            Forall(params.clone(), Box::new(t.prim))
+        }
+        prim => prim.clone(),
+    };
+    parser::TypeExpr { qual, prim }
+}
+
+/// 具体化されなかった型変数のうち、型tに現れるものを量化する
+///
+/// ```text
+/// (const un 1) : forall b : un. un (b -> un int)
+/// ```
+fn generalize(
+    params: &[parser::TypeParam],
+    subst: &Subst,
+    t: parser::TypeExpr,
+) -> parser::TypeExpr {
+    let mut rest: Vec<parser::TypeParam> = params
+        .iter()
+        .filter(|p| p.bound.is_some() && !subst.types.contains_key(&p.name) && occurs(&p.name, &t))
+        .cloned()
+        .collect();
+    if rest.is_empty() {
+        return t;
+    }
+    let prim = match t.prim {
+        parser::PrimType::Forall(inner, prim) => {
+            rest.extend(inner);
+            prim
+        }
+        prim => Box::new(prim),
+    };
+    parser::TypeExpr {
+        qual: t.qual,
+        prim: parser::PrimType::Forall(rest, prim),
+    }
+}
+
+/// 型変数vが型tに現れるか
+fn occurs(v: &str, t: &parser::TypeExpr) -> bool {
+    use parser::PrimType::*;
+    match &t.prim {
+        Var(name) => name == v,
+        Pair(t1, t2) | Sum(t1, t2) | Arrow(t1, t2) => occurs(v, t1) || occurs(v, t2),
+        Forall(params, prim) => {
+            !params.iter().any(|p| p.name == v)
+                && occurs(
+                    v,
+                    &parser::TypeExpr {
+                        qual: parser::Qual::Un,
+                        prim: (**prim).clone(),
+                    },
+                )
+        }
+        Bool | Int | Unit | Alias(_) => false,
+    }
+}
+
+/// t1とt2の最小の上界。分岐の型を合わせるために用いる
+fn join_type(t1: &parser::TypeExpr, t2: &parser::TypeExpr) -> Option<parser::TypeExpr> {
+    bound(t1, t2, true)
+}
+
+/// t1とt2の最大の下界
+fn meet_type(t1: &parser::TypeExpr, t2: &parser::TypeExpr) -> Option<parser::TypeExpr> {
+    bound(t1, t2, false)
+}
+
+/// upperの場合は最小の上界、そうでない場合は最大の下界。関数型の引数は逆の境界をとる
+fn bound(t1: &parser::TypeExpr, t2: &parser::TypeExpr, upper: bool) -> Option<parser::TypeExpr> {
+    use parser::PrimType::*;
+    let (qual, same, opposite): (_, fn(_, _) -> _, fn(_, _) -> _) = if upper {
+        (t1.qual.join(&t2.qual), join_type, meet_type)
+    } else {
+        (t1.qual.meet(&t2.qual), meet_type, join_type)
+    };
+    let prim = match (&t1.prim, &t2.prim) {
+        (Pair(a1, b1), Pair(a2, b2)) => Pair(Box::new(same(a1, a2)?), Box::new(same(b1, b2)?)),
+        (Sum(a1, b1), Sum(a2, b2)) => Sum(Box::new(same(a1, a2)?), Box::new(same(b1, b2)?)),
+        (Arrow(a1, r1), Arrow(a2, r2)) => {
+            Arrow(Box::new(opposite(a1, a2)?), Box::new(same(r1, r2)?))
+        }
+        (p1, p2) if p1 == p2 => p1.clone(),
+        _ => return None,
+    };
+    Some(parser::TypeExpr { qual, prim })
+}
+
+/// 逐次実行の型付け
+///
+/// expr1の値は捨てられるため、un unit型か、捨てられるaff型でなければならない
+fn typing_seq(expr: &mut parser::SeqExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    let t1 = typing(&mut expr.expr1, env, depth)?;
+    let discardable = t1.qual == parser::Qual::Aff
+        || (t1.qual == parser::Qual::Un && t1.prim == parser::PrimType::Unit);
+    if !discardable {
+        return Err(Error::new(ErrorKind::NotDiscardable(t1), expr.expr1.span()));
+    }
+    typing(&mut expr.expr2, env, depth)
+}
+
 /// envの型付け
-fn typing_env<'a>(expr: &parser::EnvExpr, env: &mut TypeEnv, depth: usize) -> TResult<'a> {
-    let t = typing(&expr.expr, env, depth)?;
+fn typing_env(expr: &mut parser::EnvExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    let t = typing(&mut expr.expr, env, depth)?;
 
     Ok(t)
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{parser, typing::*};
+
+    // エラーの種類を直接比較するため、Boxを外して返す
+    #[allow(clippy::result_large_err)]
+    fn typing_str(input: &str) -> Result<parser::TypeExpr, ErrorKind> {
+        let (_, mut expr) = parser::parse(input).unwrap();
+        crate::alias::resolve(&mut expr).map_err(|e| *e.kind)?;
+        typing(&mut expr, &mut TypeEnv::new(), 0).map_err(|e| *e.kind)
+    }
+
+    fn ty(input: &str) -> parser::TypeExpr {
+        parser::parse_type(input).unwrap().1
+    }
+
+    #[test]
+    fn test_typing_int() {
+        // un intは複製できる
+        let t = typing_str("let x : un int = un 1; un <(x + x), (x < un 2)>").unwrap();
+        assert_eq!("un (un int * un bool)", t.to_string());
+
+        // lin intは複製できない
+        assert!(typing_str("let x : lin int = lin 1; (x + x)").is_err());
+
+        // 結果の修飾子は制約の強い方
+        let t = typing_str("let x : lin int = lin 1; (x * un 2)").unwrap();
+        assert_eq!("lin int", t.to_string());
+        let t = typing_str("let x : aff int = aff 1; (x == un 2)").unwrap();
+        assert_eq!("aff bool", t.to_string());
+
+        // 演算子の両辺はint
+        assert_eq!(
+            Err(ErrorKind::NotAnInt {
+                op: parser::BinOp::Add,
+                found: ty("un bool"),
+            }),
+            typing_str("(un 1 + un true)")
+        );
+    }
+
+    #[test]
+    fn test_typing_rec() {
+        let t = typing_str(
+            "let rec f : un (un int -> un int) = un fn n : un int {
+                if (n == un 0) { un 0 } else { (f (n - un 1)) }
+            };
+            (f un 3)",
+        )
+        .unwrap();
+        assert_eq!("un int", t.to_string());
+
+        // lin型の再帰関数は複数回呼び出せない
+        assert_eq!(
+            Err(ErrorKind::RecNotUn("f".to_string())),
+            typing_str(
+                "let rec f : lin (lin bool -> lin bool) = lin fn x : lin bool { (f x) };
+                (f lin true)"
+            )
+        );
+
+        // 再帰的な束縛の値は関数
+        assert_eq!(
+            Err(ErrorKind::RecNotFunction("x".to_string())),
+            typing_str("let rec x : un bool = un true; x")
+        );
+    }
+
+    #[test]
+    fn test_typing_case() {
+        let t = typing_str(
+            "case lin inr (un bool + un int) un 3 of inl b { b } | inr n { (n < un 5) }",
+        )
+        .unwrap();
+        assert_eq!("un bool", t.to_string());
+
+        // 両方の分岐で同じlin型の変数を消費しなければならない
+        let input = "let x : lin bool = lin true;
+            case un inl (un bool + un bool) un true of inl a { x } | inr b { lin false }";
+        assert_eq!(
+            Err(ErrorKind::BranchEnvMismatch(Branch::Case)),
+            typing_str(input)
+        );
+
+        // un型の直和はlin型を含めない
+        assert_eq!(
+            Err(ErrorKind::LinearInUnSum),
+            typing_str("un inl (lin bool + un int) lin true")
+        );
+        // 注入する値の型は直和の型と一致
+        assert_eq!(
+            Err(ErrorKind::InjectionMismatch {
+                expected: ty("un bool"),
+                found: ty("un int"),
+            }),
+            typing_str("un inl (un bool + un int) un 1")
+        );
+    }
+
+    #[test]
+    fn test_typing_restore_env() {
+        // un型の関数を型付けした後も、スコープ内のlin, aff型の変数を使える
+        let t = typing_str(
+            "let x : lin bool = lin true;
+            let y : aff bool = aff false;
+            let f = un fn z : un bool { z };
+            lin <x, y>",
+        )
+        .unwrap();
+        assert_eq!("lin (lin bool * aff bool)", t.to_string());
+
+        // 使わなかったlin型の変数は消費されていない
+        assert_eq!(
+            Err(ErrorKind::LinearNotConsumed {
+                var: "x".to_string(),
+                scope: Scope::Let,
+            }),
+            typing_str(
+                "let x : lin bool = lin true;
+                let f = un fn z : un bool { z };
+                (f un true)"
+            )
+        );
+        // un型の関数からはキャプチャできない
+        assert_eq!(
+            Err(ErrorKind::UnboundVariable("y".to_string())),
+            typing_str(
+                "let y : aff bool = aff false;
+                let f = un fn z : un bool { y };
+                y"
+            )
+        );
+    }
+
+    #[test]
+    fn test_typing_seq() {
+        let t = typing_str("let x : lin bool = lin true; free x; ()").unwrap();
+        assert_eq!("un unit", t.to_string());
+        let t = typing_str("lin fn x : lin bool { free x }").unwrap();
+        assert_eq!("lin (lin bool -> un unit)", t.to_string());
+
+        // aff型の値は捨てられる
+        assert!(typing_str("aff true; un 1").is_ok());
+        // lin型やun unit以外のun型の値は捨てられない
+        assert_eq!(
+            Err(ErrorKind::NotDiscardable(ty("lin unit"))),
+            typing_str("lin (); un 1")
+        );
+        assert!(typing_str("un true; un 1").is_err());
+    }
+
+    #[test]
+    fn test_typing_infer() {
+        // 型注釈のないletは、束縛する値の型を変数の型とする
+        let t = typing_str(
+            "let f = un fn y : lin bool { lin <y, un 1> };
+            let x = lin true;
+            (f x)",
+        )
+        .unwrap();
+        assert_eq!("lin (lin bool * un int)", t.to_string());
+        // lin型の変数は推論しても消費しなければならない
+        assert_eq!(
+            Err(ErrorKind::LinearNotConsumed {
+                var: "x".to_string(),
+                scope: Scope::Let
+            }),
+            typing_str("let x = lin true; un 1")
+        );
+
+        // 推論した型は評価で用いるため式に記録する
+        let (_, mut expr) = parser::parse("let x = lin true; x").unwrap();
+        typing(&mut expr, &mut TypeEnv::new(), 0).unwrap();
+        match expr {
+            parser::Expr::Let(e) => assert_eq!(Some(ty("lin bool")), e.inferred),
+            _ => panic!("let expression expected"),
+        }
+
+        // defも同様
+        let mut env = TypeEnv::new();
+        env.push(0);
+        let (_, mut expr) = parser::parse("def p = aff <un 1, un false>;").unwrap();
+        typing(&mut expr, &mut env, 0).unwrap();
+        assert_eq!(Some(&ty("aff (un int * un bool)")), env.get("p"));
+
+        // 再帰関数は型注釈が必要
+        assert_eq!(
+            Err(ErrorKind::RecNotAnnotated("f".to_string())),
+            typing_str("let rec f = un fn x : un int { (f x) }; un 1")
+        );
+    }
+
+    #[test]
+    fn test_typing_multi_arg() {
+        // 二つめ以降の引数を受け取る関数は、それまでの引数の修飾子のうち最も制約の強いもの
+        let t = typing_str("un fn x : lin bool, y : un bool { if y { x } else { x } }").unwrap();
+        assert_eq!("un (lin bool -> lin (un bool -> lin bool))", t.to_string());
+        let t = typing_str("un fn x : un int, y : aff int, z : un int { un 0 }").unwrap();
+        assert_eq!(
+            "un (un int -> un (aff int -> aff (un int -> un int)))",
+            t.to_string()
+        );
+
+        let t = typing_str(
+            "let f : un (un int -> un (un int -> un int)) = un fn x : un int, y : un int { (x - y) };
+            (f un 5 un 3)",
+        )
+        .unwrap();
+        assert_eq!("un int", t.to_string());
+
+        // lin型の引数をキャプチャした関数は一度しか適用できない
+        let input = "let f : un (lin bool -> lin (un bool -> lin bool)) = un fn x : lin bool, y : un bool { if y { x } else { x } };
+            let g : lin (un bool -> lin bool) = (f lin true);
+            lin <(g un true), (g un false)>";
+        assert_eq!(
+            Err(ErrorKind::AlreadyUsed("g".to_string())),
+            typing_str(input)
+        );
+
+        // 別名や型変数の引数の修飾子も、解決後の型から決める
+        let t = typing_str("type L = lin bool; un fn x : L, y : un bool { x }").unwrap();
+        assert_eq!("un (lin bool -> lin (un bool -> lin bool))", t.to_string());
+        let t = typing_str(
+            "let k : forall a : lin. un (a -> lin (un bool -> a)) = un fn x : a, y : un bool { x };
+            ((k un true) un false)",
+        )
+        .unwrap();
+        assert_eq!("un bool", t.to_string());
+    }
+
+    #[test]
+    fn test_typing_subtype() {
+        // un ≤ aff ≤ lin
+        for (t1, t2, expected) in [
+            ("un bool", "lin bool", true),
+            ("un bool", "aff bool", true),
+            ("aff bool", "lin bool", true),
+            ("lin bool", "aff bool", false),
+            ("aff int", "un int", false),
+            ("un bool", "lin int", false),
+            // ペア型と直和型は共変
+            ("un (un bool * un int)", "lin (lin bool * aff int)", true),
+            ("lin (lin bool * un int)", "lin (un bool * un int)", false),
+            ("un (un bool + un int)", "aff (aff bool + un int)", true),
+            // 関数型は引数について反変、返り値について共変
+            (
+                "un (lin bool -> un bool)",
+                "lin (un bool -> lin bool)",
+                true,
+            ),
+            ("un (un bool -> un bool)", "un (lin bool -> un bool)", false),
+            (
+                "un (lin bool -> lin bool)",
+                "un (lin bool -> un bool)",
+                false,
+            ),
+            (
+                "un (lin (un bool -> un bool) -> un int)",
+                "un (un (lin bool -> un bool) -> un int)",
+                true,
+            ),
+            (
+                "un (un (lin bool -> un bool) -> un int)",
+                "un (lin (un bool -> un bool) -> un int)",
+                false,
+            ),
+        ] {
+            assert_eq!(expected, is_subtype(&ty(t1), &ty(t2)), "{t1} <= {t2}");
+        }
+    }
+
+    #[test]
+    fn test_typing_subsumption() {
+        // lin型を受け取る関数にun型の値を渡せる
+        let t = typing_str("(lin fn x : lin bool { x } un true)").unwrap();
+        assert_eq!("lin bool", t.to_string());
+
+        // lin型の引数を受け取る関数は、un型の引数を受け取る関数として使える
+        let t = typing_str(
+            "let apply : un (un (un bool -> un bool) -> un bool) =
+                un fn f : un (un bool -> un bool) { (f un true) };
+            (apply un fn x : aff bool { un false })",
+        )
+        .unwrap();
+        assert_eq!("un bool", t.to_string());
+        // 逆は型付けできない
+        assert_eq!(
+            Err(ErrorKind::ArgumentMismatch {
+                expected: ty("un (aff bool -> un bool)"),
+                found: ty("un (un bool -> un bool)"),
+            }),
+            typing_str(
+                "let apply : un (un (aff bool -> un bool) -> un bool) =
+                    un fn f : un (aff bool -> un bool) { (f aff true) };
+                (apply un fn x : un bool { x })"
+            )
+        );
+
+        // letの型注釈は束縛する値の型の上界で、変数の型は型注釈の型
+        assert_eq!(
+            Err(ErrorKind::LinearNotConsumed {
+                var: "x".to_string(),
+                scope: Scope::Let,
+            }),
+            typing_str("let x : lin bool = un true; un 1")
+        );
+        let t = typing_str("let x : aff bool = un true; lin <x, un 1>").unwrap();
+        assert_eq!("lin (aff bool * un int)", t.to_string());
+
+        // 分岐の型は両方の分岐の型の最小の上界
+        let t = typing_str("if un true { un true } else { lin false }").unwrap();
+        assert_eq!("lin bool", t.to_string());
+        let t = typing_str(
+            "if un true {
+                un fn x : lin bool { x }
+            } else {
+                aff fn x : un bool { lin true }
+            }",
+        )
+        .unwrap();
+        assert_eq!("aff (un bool -> lin bool)", t.to_string());
+        let t = typing_str(
+            "case un inl (un bool + un int) un true of
+                inl b { un <b, un 1> } | inr n { aff <un false, n> }",
+        )
+        .unwrap();
+        assert_eq!("aff (un bool * un int)", t.to_string());
+    }
+
+    #[test]
+    fn test_typing_qual_poly() {
+        let id = "let id : forall q. un (q bool -> q bool) = un fn x : q bool { x };";
+
+        // 適用ごとに修飾子変数を引数の型に合わせて具体化
+        let t = typing_str(&format!("{id} lin <(id lin true), (id un false)>")).unwrap();
+        assert_eq!("lin (lin bool * un bool)", t.to_string());
+        assert_eq!(
+            Err(ErrorKind::ArgumentMismatch {
+                expected: ty("lin bool"),
+                found: ty("lin int"),
+            }),
+            typing_str(&format!("{id} (id lin 1)"))
+        );
+        // 具体的な型の関数として渡せる
+        let t = typing_str(&format!(
+            "{id}
+            let apply = un fn f : un (aff bool -> aff bool) {{ (f aff true) }};
+            (apply id)"
+        ))
+        .unwrap();
+        assert_eq!("aff bool", t.to_string());
+
+        // 複数箇所の修飾子変数は、最も制約の強い修飾子に具体化
+        let t = typing_str(
+            "let first : forall q. un (q (q bool * q bool) -> q bool) =
+                un fn p : q (q bool * q bool) { split p as a, b { free b; a } };
+            (first lin <un true, aff false>)",
+        )
+        .unwrap();
+        assert_eq!("lin bool", t.to_string());
+
+        // 修飾子変数の型の値はlin型と同様に一度だけ使う
+        assert_eq!(
+            Err(ErrorKind::AlreadyUsed("x".to_string())),
+            typing_str(
+                "let f : forall q. un (q bool -> un bool) = un fn x : q bool { lin <x, x> }; f"
+            )
+        );
+        assert_eq!(
+            Err(ErrorKind::LinearNotConsumed {
+                var: "x".to_string(),
+                scope: Scope::Function,
+            }),
+            typing_str(
+                "let f : forall q. un (q bool -> un bool) = un fn x : q bool { un true }; f"
+            )
+        );
+        // 任意の修飾子について成り立たない型注釈
+        assert_eq!(
+            Err(ErrorKind::TypeMismatch {
+                var: "f".to_string(),
+                expected: ty("forall q. un (q bool -> un bool)"),
+                found: ty("un (q bool -> q bool)"),
+            }),
+            typing_str("let f : forall q. un (q bool -> un bool) = un fn x : q bool { x }; un 1")
+        );
+
+        // 修飾子変数qの型の変数をキャプチャする関数は、q以上の修飾子
+        let t = typing_str(
+            "let k : forall q. un (q bool -> lin (un bool -> q bool)) = un fn x : q bool, y : un bool { x };
+            ((k un true) un false)",
+        )
+        .unwrap();
+        assert_eq!("un bool", t.to_string());
+        assert_eq!(
+            Err(ErrorKind::CaptureQualifier {
+                var: "x".to_string(),
+                ty: ty("q bool"),
+                closure: parser::Qual::Aff,
+            }),
+            typing_str(
+                "let f : forall q. un (q bool -> aff (un bool -> q bool)) =
+                    un fn x : q bool { aff fn y : un bool { x } }; f"
+            )
+        );
+        assert_eq!(
+            Err(ErrorKind::CaptureQualifier {
+                var: "x".to_string(),
+                ty: ty("q bool"),
+                closure: parser::Qual::Un,
+            }),
+            typing_str(
+                "let f : forall q. un (q bool -> un (un bool -> q bool)) =
+                    un fn x : q bool { un fn y : un bool { x } }; f"
+            )
+        );
+    }
+
+    #[test]
+    fn test_typing_type_poly() {
+        let swap = "let swap : forall a : lin, b : lin. un (lin (a * b) -> lin (b * a)) =
+            un fn p : lin (a * b) { split p as x, y { lin <y, x> } };";
+
+        let t = typing_str(&format!("{swap} swap")).unwrap();
+        assert_eq!(
+            "forall a : lin, b : lin. un (lin (a * b) -> lin (b * a))",
+            t.to_string()
+        );
+        // 適用ごとに型変数を引数の型に合わせて具体化
+        let t = typing_str(&format!(
+            "{swap} lin <(swap lin <un true, un 1>), (swap lin <un 1, lin true>)>"
+        ))
+        .unwrap();
+        assert_eq!(
+            "lin (lin (un int * un bool) * lin (lin bool * un int))",
+            t.to_string()
+        );
+        assert!(matches!(
+            typing_str(&format!("{swap} (swap un true)")),
+            Err(ErrorKind::ArgumentMismatch { .. })
+        ));
+
+        // 上限がlinの型変数の値は、lin型と同様に一度だけ使う
+        assert_eq!(
+            Err(ErrorKind::AlreadyUsed("x".to_string())),
+            typing_str(
+                "let f : forall a : lin. un (a -> lin (a * a)) = un fn x : a { lin <x, x> }; f"
+            )
+        );
+        assert_eq!(
+            Err(ErrorKind::LinearNotConsumed {
+                var: "x".to_string(),
+                scope: Scope::Function,
+            }),
+            typing_str("let f : forall a : lin. un (a -> un unit) = un fn x : a { () }; f")
+        );
+
+        // 上限がunの型変数の値は複製できるが、un型でしか具体化できない
+        let dup = "let dup : forall a : un. un (a -> un (a * a)) = un fn x : a { un <x, x> };";
+        let t = typing_str(&format!("{dup} (dup un 1)")).unwrap();
+        assert_eq!("un (un int * un int)", t.to_string());
+        assert_eq!(
+            Err(ErrorKind::BoundViolation {
+                var: "a".to_string(),
+                bound: parser::Qual::Un,
+                found: ty("lin int"),
+            }),
+            typing_str(&format!("{dup} (dup lin 1)"))
+        );
+
+        // 型変数は具体的な型として使えない
+        assert!(matches!(
+            typing_str("let f : forall a : un. un (a -> un int) = un fn x : a { (x + un 1) }; f"),
+            Err(ErrorKind::NotAnInt { .. })
+        ));
+        assert!(matches!(
+            typing_str("let f : forall a : un. un (a -> un int) = un fn x : a { x }; f"),
+            Err(ErrorKind::TypeMismatch { .. })
+        ));
+
+        // 具体化されなかった型変数は、適用結果の型で量化する
+        let konst = "let const : forall a : un, b : un. un (a -> un (b -> a)) =
+            un fn x : a { un fn y : b { x } };";
+        let t = typing_str(&format!("{konst} (const un 1)")).unwrap();
+        assert_eq!("forall b : un. un (b -> un int)", t.to_string());
+        let t = typing_str(&format!("{konst} (const un 1 un true)")).unwrap();
+        assert_eq!("un int", t.to_string());
+    }
+
+    #[test]
+    fn test_typing_error_kind() {
+        // 未定義の変数と消費済みの変数
+        assert_eq!(
+            Err(ErrorKind::UnboundVariable("y".to_string())),
+            typing_str("y")
+        );
+        assert_eq!(
+            Err(ErrorKind::AlreadyUsed("x".to_string())),
+            typing_str("let x : lin bool = lin true; lin <x, x>")
+        );
+
+        // 消費されていないlin型の変数
+        assert_eq!(
+            Err(ErrorKind::LinearNotConsumed {
+                var: "x".to_string(),
+                scope: Scope::Let,
+            }),
+            typing_str("let x : lin bool = lin true; un true")
+        );
+        assert_eq!(
+            Err(ErrorKind::LinearNotConsumed {
+                var: "x".to_string(),
+                scope: Scope::Function,
+            }),
+            typing_str("lin fn x : lin bool { un true }")
+        );
+
+        // 型の不一致
+        assert_eq!(
+            Err(ErrorKind::TypeMismatch {
+                var: "x".to_string(),
+                expected: ty("un bool"),
+                found: ty("lin bool"),
+            }),
+            typing_str("let x : un bool = lin true; x")
+        );
+        assert_eq!(
+            Err(ErrorKind::ArgumentMismatch {
+                expected: ty("un bool"),
+                found: ty("un int"),
+            }),
+            typing_str("(un fn x : un bool { x } un 1)")
+        );
+        assert_eq!(
+            Err(ErrorKind::NotAFunction(ty("un bool"))),
+            typing_str("(un true un false)")
+        );
+        assert_eq!(
+            Err(ErrorKind::BranchTypeMismatch(Branch::If)),
+            typing_str("if un true { un true } else { un 1 }")
+        );
+        assert_eq!(
+            Err(ErrorKind::BranchEnvMismatch(Branch::If)),
+            typing_str("let x : lin bool = lin true; if un true { x } else { lin false }")
+        );
+    }
+}
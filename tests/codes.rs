@@ -0,0 +1,86 @@
+//! codes/*.linのスナップショットテスト
+//!
+//! 各プログラムをパース、型付け、評価した結果を、同じディレクトリの
+//! `.expected`ファイルと比較する。出力はREPLと同じ形式とする。
+//! repl*.linはREPLへの入力とみなし、各行を同じ環境で順に実行する。
+//!
+//! `BLESS=1 cargo test --test codes`で`.expected`ファイルを現在の出力で書き換える。
+
+use linzin::Interpreter;
+use std::{env, fs, path::Path};
+
+/// プログラムをパース、型付け、評価し、結果を文字列で返す。エラーの場合はそこで打ち切る
+fn run(src: &str, interp: &mut Interpreter) -> String {
+    let mut expr = match linzin::parse(src) {
+        Ok(expr) => expr,
+        Err(e) => return format!("{}:\n{}", e.parse_label(), e.render(src)),
+    };
+
+    let ty = match interp.typecheck(&mut expr) {
+        Ok(ty) => ty,
+        Err(e) => return format!("typing error:\n{}", e.render(src)),
+    };
+    let mut out = format!("[Type]\n{ty}\n");
+
+    match interp.eval(&expr) {
+        Ok(v) => out.push_str(&format!("[Evaluation]\n{v}\n")),
+        Err(e) => out.push_str(&format!("evaluation error:\n{}", e.render(src))),
+    }
+    out
+}
+
+#[test]
+fn test_codes() {
+    let bless = env::var_os("BLESS").is_some();
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("codes");
+
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "lin"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no programs in {}", dir.display());
+
+    // 一致しなかったファイルをまとめて報告する
+    let mut failures = Vec::new();
+    for path in paths {
+        let src = fs::read_to_string(&path).unwrap();
+        let repl = path
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("repl");
+        let actual = if repl {
+            let mut interp = Interpreter::new();
+            src.lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| run(line, &mut interp))
+                .collect()
+        } else {
+            run(&src, &mut Interpreter::new())
+        };
+        let expected_path = path.with_extension("expected");
+
+        if bless {
+            fs::write(&expected_path, &actual).unwrap();
+            continue;
+        }
+        match fs::read_to_string(&expected_path) {
+            Ok(expected) if expected == actual => (),
+            Ok(expected) => failures.push(format!(
+                "{}:\n--- expected\n{expected}--- actual\n{actual}",
+                path.display()
+            )),
+            Err(_) => failures.push(format!("{}: no expected file", expected_path.display())),
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{}\n{} program(s) differ from the expected output; run `BLESS=1 cargo test --test codes` to update",
+        failures.join("\n"),
+        failures.len()
+    );
+}